@@ -25,6 +25,13 @@ pub(crate) const fn get_bit_range(original: u8, range: RangeInclusive<usize>) ->
     (original & mask) >> *range.start()
 }
 
+/// Set the range of bits defined by `range` of `original` to the lowest bits of `value`
+pub(crate) fn set_bit_range(original: &mut u8, range: RangeInclusive<usize>, value: u8) {
+    for (offset, index) in range.enumerate() {
+        set_bit(original, index, get_bit(value, offset));
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -56,4 +63,15 @@ mod test {
         assert_eq!(get_bit_range(0b11001100u8, 4..=4), 0);
         assert_eq!(get_bit_range(0b11001100u8, 0..=7), 0b11001100u8);
     }
+
+    #[test]
+    fn test_set_bit_range() {
+        let mut byte = 0b11001100u8;
+        set_bit_range(&mut byte, 1..=2, 0b01);
+        assert_eq!(byte, 0b11001010u8);
+        set_bit_range(&mut byte, 4..=7, 0b11110101);
+        assert_eq!(byte, 0b01011010u8);
+        set_bit_range(&mut byte, 0..=7, 0b10000001);
+        assert_eq!(byte, 0b10000001u8);
+    }
 }
@@ -0,0 +1,368 @@
+//! Blocking interface for ports implementing [std::io::Read] and [std::io::Write]
+extern crate std;
+
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll};
+use core::time::Duration;
+use std::io;
+use std::time::Instant;
+
+use futures::task::noop_waker_ref;
+use futures::{Future, Stream};
+
+use crate::incoming_package::{CustomPackages, IncomingPackage, StorageDataIdentifiers};
+use crate::outgoing_package::OutgoingPackage;
+use crate::traits::AsyncReadWrite;
+use crate::{
+    DateTime, Error, PulseOximeter, RealTimeDataStream, RealTimeDevice, Result, StorageDataStream,
+    Timer,
+};
+
+/// Represents a blocking connection with a pulse oximeter.
+///
+/// Provides the same operations as [PulseOximeter], but blocks until they are completed.
+/// Read timeouts of the port (e.g. configured with `serialport::SerialPort::set_timeout()`) are
+/// reported as [Error::Timeout]. An operation which timed out can simply be retried, partially
+/// received packages are completed by the next call.
+pub struct BlockingPulseOximeter<T: io::Read + io::Write> {
+    inner: PulseOximeter<BlockingPort<T>>,
+}
+
+/// Port which makes a blocking port usable by [PulseOximeter]
+///
+/// All operations complete immediately, so futures using it never return `Poll::Pending`.
+/// Operations which are interrupted ([io::ErrorKind::Interrupted]) are retried.
+pub struct BlockingPort<T>(T);
+
+/// Run `operation` until it is not interrupted, like [io::Read::read_exact()] does
+fn retry_interrupted<R>(mut operation: impl FnMut() -> io::Result<R>) -> io::Result<R> {
+    loop {
+        match operation() {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+impl<T: io::Read + io::Write> AsyncReadWrite for BlockingPort<T> {
+    type Error = io::Error;
+
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let port = &mut self.get_mut().0;
+        Poll::Ready(retry_interrupted(|| port.read(buf)))
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let port = &mut self.get_mut().0;
+        Poll::Ready(retry_interrupted(|| port.write(buf)))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let port = &mut self.get_mut().0;
+        Poll::Ready(retry_interrupted(|| port.flush()))
+    }
+}
+
+impl<T> Unpin for BlockingPort<T> {}
+
+/// [Timer] for [BlockingPulseOximeter], whose delays are checked whenever they are polled.
+///
+/// The delays never wake the task, which is not necessary because the operations of a
+/// [BlockingPulseOximeter] block until the port returns, and are polled again afterwards.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BlockingTimer;
+
+/// Delay of [BlockingTimer]
+#[derive(Debug)]
+pub struct BlockingSleep(Instant);
+
+impl Future for BlockingSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.0 {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Timer for BlockingTimer {
+    type Sleep = BlockingSleep;
+
+    fn sleep(&mut self, duration: Duration) -> BlockingSleep {
+        BlockingSleep(Instant::now() + duration)
+    }
+}
+
+/// Iterator over the items of a stream of [BlockingPulseOximeter]
+pub struct BlockingIter<S>(S);
+
+impl<R, S: Stream<Item = Result<R, io::Error>> + Unpin> Iterator for BlockingIter<S> {
+    type Item = Result<R, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        match Pin::new(&mut self.0).poll_next(&mut cx) {
+            Poll::Ready(item) => item.map(|result| result.map_err(timeout)),
+            Poll::Pending => unreachable!("blocking port returned `Poll::Pending`"),
+        }
+    }
+}
+
+/// Run `future`, which must not return `Poll::Pending` because it only uses a [BlockingPort]
+fn block<R>(future: impl Future<Output = Result<R, io::Error>>) -> Result<R, io::Error> {
+    let mut cx = Context::from_waker(noop_waker_ref());
+    match pin!(future).poll(&mut cx) {
+        Poll::Ready(result) => result.map_err(timeout),
+        Poll::Pending => unreachable!("blocking port returned `Poll::Pending`"),
+    }
+}
+
+/// Report read timeouts of the port as [Error::Timeout]
+fn timeout(error: Error<io::Error>) -> Error<io::Error> {
+    match error {
+        Error::DeviceIOError { source }
+            if matches!(source.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) =>
+        {
+            Error::Timeout
+        }
+        error => error,
+    }
+}
+
+impl<T: io::Read + io::Write> BlockingPulseOximeter<T> {
+    /// Create a pulse oximeter interface, using the given `port` for communication.
+    pub fn new(port: T) -> Self {
+        Self {
+            inner: PulseOximeter::new(BlockingPort(port)),
+        }
+    }
+
+    /// The port used for communication.
+    pub fn port(&mut self) -> &mut T {
+        &mut self.inner.port.0
+    }
+
+    /// Enable or disable resynchronization of the incoming byte stream.
+    ///
+    /// See [PulseOximeter::set_resynchronize()] for details.
+    pub fn set_resynchronize(&mut self, resynchronize: bool) {
+        self.inner.set_resynchronize(resynchronize);
+    }
+
+    /// Decode vendor-specific packages as [IncomingPackage::Custom].
+    ///
+    /// See [PulseOximeter::set_custom_packages()] for details.
+    pub fn set_custom_packages(&mut self, custom: CustomPackages) {
+        self.inner.set_custom_packages(custom);
+    }
+
+    /// Total number of incoming bytes which were dropped during resynchronization.
+    pub fn skipped_bytes(&self) -> usize {
+        self.inner.skipped_bytes()
+    }
+
+    /// Send a package to the device.
+    pub fn send_package<P: OutgoingPackage>(&mut self, package: P) -> Result<(), io::Error> {
+        block(self.inner.send_package(package))
+    }
+
+    /// Receive the next package from the device.
+    pub fn receive_package(&mut self) -> Result<IncomingPackage, io::Error> {
+        block(self.inner.receive_package())
+    }
+
+    /// Receive real time data.
+    ///
+    /// See [PulseOximeter::realtime()](RealTimeDevice::realtime()) for details. The device is kept
+    /// alive whenever the next sample is read, so the read timeout of the port should be shorter
+    /// than 4 seconds.
+    pub fn realtime(
+        &mut self,
+    ) -> BlockingIter<RealTimeDataStream<'_, PulseOximeter<BlockingPort<T>>, BlockingTimer>> {
+        BlockingIter(self.inner.realtime(BlockingTimer))
+    }
+
+    /// Stop sending real time data and wait until the device is free.
+    pub fn stop_real_time_data(&mut self) -> Result<(), io::Error> {
+        block(self.inner.stop_real_time_data())
+    }
+
+    /// Ask for the device identifier.
+    pub fn device_identifier(&mut self) -> Result<[u8; 7], io::Error> {
+        block(self.inner.device_identifier())
+    }
+
+    /// Ask for the number of users.
+    pub fn user_amount(&mut self) -> Result<u8, io::Error> {
+        block(self.inner.user_amount())
+    }
+
+    /// Ask for the number of storage segments of user `user_index`.
+    pub fn segment_amount(&mut self, user_index: u8) -> Result<u8, io::Error> {
+        block(self.inner.segment_amount(user_index))
+    }
+
+    /// Ask for the start time of a storage segment.
+    pub fn storage_start_time(
+        &mut self,
+        user_index: u8,
+        segment: u8,
+    ) -> Result<DateTime, io::Error> {
+        block(self.inner.storage_start_time(user_index, segment))
+    }
+
+    /// Ask for the data length (in bytes) of a storage segment.
+    pub fn storage_length(&mut self, user_index: u8, segment: u8) -> Result<u32, io::Error> {
+        block(self.inner.storage_length(user_index, segment))
+    }
+
+    /// Ask for the storage data identifiers of a storage segment.
+    pub fn storage_identifiers(
+        &mut self,
+        user_index: u8,
+        segment: u8,
+    ) -> Result<StorageDataIdentifiers, io::Error> {
+        block(self.inner.storage_identifiers(user_index, segment))
+    }
+
+    /// Download the samples of a storage segment.
+    ///
+    /// See [PulseOximeter::storage_data()] for details.
+    pub fn storage_data(
+        &mut self,
+        user_index: u8,
+        segment: u8,
+    ) -> Result<BlockingIter<StorageDataStream<'_, BlockingPort<T>>>, io::Error> {
+        block(self.inner.storage_data(user_index, segment)).map(BlockingIter)
+    }
+
+    /// Download the samples of the storage segment described by `identifiers`.
+    ///
+    /// See [PulseOximeter::storage_data_with()] for details.
+    pub fn storage_data_with(
+        &mut self,
+        identifiers: StorageDataIdentifiers,
+    ) -> Result<BlockingIter<StorageDataStream<'_, BlockingPort<T>>>, io::Error> {
+        block(self.inner.storage_data_with(identifiers)).map(BlockingIter)
+    }
+
+    /// Delete a storage segment.
+    pub fn delete_segment(&mut self, user_index: u8, segment: u8) -> Result<(), io::Error> {
+        block(self.inner.delete_segment(user_index, segment))
+    }
+
+    /// Set the date and time of the device clock.
+    pub fn set_date_time(&mut self, date_time: DateTime) -> Result<(), io::Error> {
+        block(self.inner.set_date_time(date_time))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::incoming_package::{StorageData, StorageDataLength, UserAmount};
+
+    /// Port which times out as soon as all prepared responses were read
+    struct Port {
+        responses: VecDeque<u8>,
+        written: Vec<u8>,
+        /// Number of reads and writes which are interrupted before they succeed
+        interruptions: usize,
+    }
+
+    impl Port {
+        fn new(responses: &[IncomingPackage]) -> Self {
+            Self {
+                responses: responses.iter().flat_map(|p| p.to_bytes().to_vec()).collect(),
+                written: Vec::new(),
+                interruptions: 0,
+            }
+        }
+
+        fn interrupt(&mut self) -> io::Result<()> {
+            if self.interruptions > 0 {
+                self.interruptions -= 1;
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            Ok(())
+        }
+    }
+
+    impl io::Read for Port {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt()?;
+            if self.responses.is_empty() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.responses.read(buf)
+        }
+    }
+
+    impl io::Write for Port {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.interrupt()?;
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_request_and_timeout() {
+        let mut device =
+            BlockingPulseOximeter::new(Port::new(&[IncomingPackage::UserAmount(UserAmount {
+                total_user: 2,
+            })]));
+        assert_eq!(device.user_amount().unwrap(), 2);
+        assert!(matches!(device.user_amount(), Err(Error::Timeout)));
+        assert_eq!(device.port().written.len(), 18);
+    }
+
+    #[test]
+    fn test_retry_interrupted() {
+        let mut port = Port::new(&[IncomingPackage::UserAmount(UserAmount { total_user: 2 })]);
+        port.interruptions = 3;
+        let mut device = BlockingPulseOximeter::new(port);
+        assert_eq!(device.user_amount().unwrap(), 2);
+        device.port().interruptions = 1;
+        assert!(matches!(device.user_amount(), Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn test_storage_data() {
+        let mut device = BlockingPulseOximeter::new(Port::new(&[
+            IncomingPackage::StorageDataLength(StorageDataLength {
+                user_index: 0,
+                data_segment: 0,
+                length: 4,
+            }),
+            IncomingPackage::StorageData(StorageData {
+                spo2_1: 97,
+                pulse_rate_1: 60,
+                spo2_2: 98,
+                pulse_rate_2: 61,
+                spo2_3: 0,
+                pulse_rate_3: 0,
+            }),
+        ]));
+        let samples = device.storage_data(0, 0).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[1].pulse_rate, 61);
+    }
+}
@@ -0,0 +1,370 @@
+//! Capture and replay of the raw bytes exchanged with a device
+//!
+//! A capture is a text file with one record per line: the time since the capture was started (in
+//! seconds), the direction (`R` for bytes read from the device, `W` for bytes written to the
+//! device) and the bytes in hexadecimal, e.g. `0.012345 R 0C 80`. Empty lines and lines starting
+//! with `#` are ignored.
+extern crate std;
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use std::boxed::Box;
+use std::io;
+use std::io::BufRead;
+use std::time::Instant;
+use std::vec::Vec;
+
+use futures::ready;
+
+use crate::traits::AsyncReadWrite;
+
+/// Direction of a captured transfer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// Bytes read from the device
+    Read,
+    /// Bytes written to the device
+    Write,
+}
+
+/// Single transfer of a capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Time since the capture was started
+    pub time: Duration,
+    /// Direction of the transfer
+    pub direction: Direction,
+    /// Transferred bytes
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Read => 'R',
+            Direction::Write => 'W',
+        };
+        write!(f, "{}.{:06} {direction}", self.time.as_secs(), self.time.subsec_micros())?;
+        for byte in &self.bytes {
+            write!(f, " {byte:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Record {
+    /// Parse a record line, as written by [Capture]
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let time = Duration::try_from_secs_f64(fields.next()?.parse().ok()?).ok()?;
+        let direction = match fields.next()? {
+            "R" => Direction::Read,
+            "W" => Direction::Write,
+            _ => return None,
+        };
+        let bytes = fields
+            .map(|byte| u8::from_str_radix(byte, 16).ok())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Record {
+            time,
+            direction,
+            bytes,
+        })
+    }
+}
+
+/// Read all records of a capture.
+pub fn read_capture(reader: impl BufRead) -> io::Result<Vec<Record>> {
+    let mut records = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let record = Record::parse(line).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                std::format!("invalid capture record in line {}: '{line}'", index + 1),
+            )
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Port which writes every byte read from and written to `port` to a capture.
+///
+/// Each record is written as soon as the transfer completed, so `writer` should not be buffered if
+/// the capture has to survive a crash. Failing to write the capture fails the transfer.
+pub struct Capture<T, W> {
+    port: T,
+    writer: W,
+    start: Instant,
+}
+
+impl<T, W> Capture<T, W>
+where
+    T: AsyncReadWrite + Unpin,
+    T::Error: From<io::Error>,
+    W: io::Write + Unpin,
+{
+    /// Capture the communication over `port` to `writer`.
+    pub fn new(port: T, writer: W) -> Self {
+        Self {
+            port,
+            writer,
+            start: Instant::now(),
+        }
+    }
+
+    fn record(&mut self, direction: Direction, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let record = Record {
+            time: self.start.elapsed(),
+            direction,
+            bytes: bytes.to_vec(),
+        };
+        std::writeln!(self.writer, "{record}")
+    }
+}
+
+impl<T, W> AsyncReadWrite for Capture<T, W>
+where
+    T: AsyncReadWrite + Unpin,
+    T::Error: From<io::Error>,
+    W: io::Write + Unpin,
+{
+    type Error = T::Error;
+
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, T::Error>> {
+        let this = self.get_mut();
+        let count = ready!(Pin::new(&mut this.port).poll_read(cx, buf))?;
+        this.record(Direction::Read, &buf[..count.min(buf.len())])?;
+        Poll::Ready(Ok(count))
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, T::Error>> {
+        let this = self.get_mut();
+        let count = ready!(Pin::new(&mut this.port).poll_write(cx, buf))?;
+        this.record(Direction::Write, &buf[..count.min(buf.len())])?;
+        Poll::Ready(Ok(count))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), T::Error>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.port).poll_flush(cx))?;
+        this.writer.flush()?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Port which plays back a capture, e.g. to reproduce a session without the device.
+///
+/// Captured reads are returned in order, with the captured delays between the records divided by
+/// `speed`. A read is only returned after as many bytes were written as had been written before it
+/// in the capture, so answers never overtake their requests. Written bytes are discarded. Reading
+/// beyond the end of the capture fails with [io::ErrorKind::UnexpectedEof].
+///
+/// `sleep` creates the delays, e.g. `tokio::time::sleep`.
+pub struct Replay<F, S> {
+    records: Vec<Record>,
+    speed: f64,
+    sleep: F,
+    delay: Option<Pin<Box<S>>>,
+    /// Index of the current record
+    index: usize,
+    /// Number of bytes of the current record which were already read
+    offset: usize,
+    /// Time of the last record which was completed
+    time: Duration,
+    /// Number of bytes written to the port
+    written: usize,
+    /// Number of bytes written in the completed records
+    captured_written: usize,
+    /// Task waiting for a write
+    waker: Option<Waker>,
+}
+
+impl<F: FnMut(Duration) -> S + Unpin, S: Future<Output = ()>> Replay<F, S> {
+    /// Play back `records` at `speed` times the captured speed.
+    ///
+    /// A `speed` of [f64::INFINITY] plays back the capture without any delays.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `speed` is not positive.
+    pub fn new(records: Vec<Record>, speed: f64, sleep: F) -> Self {
+        assert!(speed > 0.0, "replay speed must be positive, got {speed}");
+        Self {
+            records,
+            speed,
+            sleep,
+            delay: None,
+            index: 0,
+            offset: 0,
+            time: Duration::ZERO,
+            written: 0,
+            captured_written: 0,
+            waker: None,
+        }
+    }
+
+    /// Finish the current record
+    fn complete(&mut self) {
+        let record = &self.records[self.index];
+        if record.direction == Direction::Write {
+            self.captured_written += record.bytes.len();
+        }
+        self.time = record.time;
+        self.index += 1;
+        self.offset = 0;
+    }
+}
+
+impl<F: FnMut(Duration) -> S + Unpin, S: Future<Output = ()>> AsyncReadWrite for Replay<F, S> {
+    type Error = io::Error;
+
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let record = match this.records.get(this.index) {
+                Some(record) => record,
+                None => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "end of capture reached",
+                    )))
+                }
+            };
+            if record.direction == Direction::Write {
+                if this.written < this.captured_written + record.bytes.len() {
+                    this.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+                this.complete();
+                continue;
+            }
+            if this.offset == 0 {
+                if this.delay.is_none() && this.speed.is_finite() {
+                    let delay = record.time.saturating_sub(this.time).div_f64(this.speed);
+                    this.delay = Some(Box::pin((this.sleep)(delay)));
+                }
+                if let Some(delay) = &mut this.delay {
+                    ready!(delay.as_mut().poll(cx));
+                    this.delay = None;
+                }
+            }
+            let bytes = &record.bytes[this.offset..];
+            let count = bytes.len().min(buf.len());
+            buf[..count].copy_from_slice(&bytes[..count]);
+            this.offset += count;
+            if this.offset == record.bytes.len() {
+                this.complete();
+            }
+            return Poll::Ready(Ok(count));
+        }
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.written += buf.len();
+        if let Some(waker) = this.waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+    use futures::future;
+
+    use super::*;
+    use crate::incoming_package::{IncomingPackage, UserAmount};
+    use crate::mock::MockPort;
+    use crate::PulseOximeter;
+
+    #[test]
+    fn test_record() {
+        let record = Record {
+            time: Duration::from_micros(1_012_345),
+            direction: Direction::Read,
+            bytes: std::vec![0x0C, 0x80],
+        };
+        assert_eq!(std::format!("{record}"), "1.012345 R 0C 80");
+        assert_eq!(Record::parse("1.012345 R 0C 80"), Some(record));
+        assert_eq!(Record::parse("1.0 X 0C"), None);
+    }
+
+    #[test]
+    fn test_capture_and_replay() {
+        let package = IncomingPackage::UserAmount(UserAmount { total_user: 3 });
+        let bytes = package.to_bytes();
+        let port = MockPort::new(&[package]);
+        let mut device = PulseOximeter::new(Capture::new(port, Vec::new()));
+        assert_eq!(block_on(device.user_amount()).unwrap(), 3);
+        let capture = device.port.writer;
+
+        let records = read_capture(&capture[..]).unwrap();
+        assert_eq!(records[0].direction, Direction::Write);
+        let read = records[1..]
+            .iter()
+            .inspect(|record| assert_eq!(record.direction, Direction::Read))
+            .flat_map(|record| record.bytes.iter().copied())
+            .collect::<Vec<_>>();
+        assert_eq!(read, &*bytes);
+
+        let mut device =
+            PulseOximeter::new(Replay::new(records, f64::INFINITY, |_| future::ready(())));
+        assert_eq!(block_on(device.user_amount()).unwrap(), 3);
+        let error = block_on(device.receive_package()).unwrap_err();
+        assert!(matches!(error, crate::Error::DeviceIOError { source }
+            if source.kind() == io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_replay_waits_for_write() {
+        let records = read_capture(&b"# comment\n0.1 W 81\n0.2 R 0C 80\n"[..]).unwrap();
+        let mut port = Replay::new(records, 2.0, |delay| {
+            assert_eq!(delay, Duration::from_millis(50));
+            future::ready(())
+        });
+        let mut buf = [0; 4];
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(Pin::new(&mut port).poll_read(&mut cx, &mut buf).is_pending());
+        assert!(Pin::new(&mut port).poll_write(&mut cx, &[0x81]).is_ready());
+        match Pin::new(&mut port).poll_read(&mut cx, &mut buf) {
+            Poll::Ready(Ok(2)) => assert_eq!(buf[..2], [0x0C, 0x80]),
+            result => panic!("unexpected result {result:?}"),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "must be positive")]
+    fn test_replay_nan_speed() {
+        Replay::new(Vec::new(), f64::NAN, |_| future::ready(()));
+    }
+}
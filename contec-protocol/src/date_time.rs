@@ -0,0 +1,65 @@
+/// Date and time of the device clock
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DateTime {
+    /// Year
+    pub year: u16,
+    /// Month (`1..=12`)
+    pub month: u8,
+    /// Day of the month (`1..=31`)
+    pub day: u8,
+    /// Hour
+    pub hour: u8,
+    /// Minute
+    pub minute: u8,
+    /// Second
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Day of the week, starting with `0` for Sunday, `None` if the month is not in `1..=12`
+    pub fn weekday(&self) -> Option<u8> {
+        // Sakamoto's method
+        const OFFSETS: [i32; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+        let offset = OFFSETS.get(usize::from(self.month).checked_sub(1)?)?;
+        // January and February count to the previous year, which is -1 for year 0
+        let year = i32::from(self.year) - i32::from(self.month < 3);
+        let day = year + year.div_euclid(4) - year.div_euclid(100)
+            + year.div_euclid(400)
+            + offset
+            + i32::from(self.day);
+        Some(day.rem_euclid(7) as u8)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn date(year: u16, month: u8, day: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        }
+    }
+
+    #[test]
+    fn test_weekday() {
+        assert_eq!(date(2022, 6, 20).weekday(), Some(1));
+        assert_eq!(date(2000, 1, 1).weekday(), Some(6));
+        assert_eq!(date(2024, 2, 29).weekday(), Some(4));
+        assert_eq!(date(2026, 10, 18).weekday(), Some(0));
+        // 1 January 0 of the proleptic Gregorian calendar was a Saturday
+        assert_eq!(date(0, 1, 1).weekday(), Some(6));
+    }
+
+    #[test]
+    fn test_invalid_month() {
+        assert_eq!(date(2022, 0, 1).weekday(), None);
+        assert_eq!(date(2022, 13, 1).weekday(), None);
+    }
+}
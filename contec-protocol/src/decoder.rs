@@ -0,0 +1,166 @@
+use core::cmp::min;
+use core::convert::Infallible;
+use core::task::Poll;
+
+use crate::incoming_package::{CustomPackages, IncomingPackage, IncomingStateMachine};
+use crate::Result;
+
+/// Push-based decoder for packages sent by the device.
+///
+/// In contrast to [PulseOximeter](crate::PulseOximeter), the decoder does not perform any I/O.
+/// Received bytes are passed to [Decoder::feed()], which returns all packages completed by these
+/// bytes. Incomplete packages are kept until the next call.
+///
+/// ```
+/// # use contec_protocol::Decoder;
+/// # use contec_protocol::incoming_package::IncomingPackage;
+/// let mut decoder = Decoder::new();
+/// // First half of a FreeFeedback package
+/// assert!(decoder.feed(&[0x0C]).next().is_none());
+/// // Second half of the package
+/// assert!(matches!(decoder.feed(&[0x80]).next(), Some(Ok(IncomingPackage::FreeFeedback(_)))));
+/// ```
+pub struct Decoder {
+    incoming: IncomingStateMachine,
+}
+
+impl Decoder {
+    /// Create a new decoder, which has not received any bytes yet.
+    pub const fn new() -> Self {
+        Self {
+            incoming: IncomingStateMachine::without_read_ahead(),
+        }
+    }
+
+    /// Enable or disable resynchronization of the incoming byte stream.
+    ///
+    /// See [IncomingStateMachine::set_resynchronize()] for details.
+    pub fn set_resynchronize(&mut self, resynchronize: bool) {
+        self.incoming.set_resynchronize(resynchronize);
+    }
+
+    /// Decode vendor-specific packages as [IncomingPackage::Custom].
+    ///
+    /// See [IncomingStateMachine::set_custom_packages()] for details.
+    pub fn set_custom_packages(&mut self, custom: CustomPackages) {
+        self.incoming.set_custom_packages(custom);
+    }
+
+    /// Total number of incoming bytes which were dropped during resynchronization.
+    pub fn skipped_bytes(&self) -> usize {
+        self.incoming.skipped_bytes()
+    }
+
+    /// Decode the given bytes.
+    ///
+    /// Returns an iterator over all packages which are completed by `bytes`. The bytes are only
+    /// consumed as far as the iterator is advanced; if it is dropped early, use
+    /// [Packages::remaining()] to get the unprocessed bytes.
+    pub fn feed<'a>(&'a mut self, bytes: &'a [u8]) -> Packages<'a> {
+        Packages {
+            decoder: self,
+            bytes,
+        }
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Iterator over the packages decoded by [Decoder::feed()].
+pub struct Packages<'a> {
+    decoder: &'a mut Decoder,
+    bytes: &'a [u8],
+}
+
+impl<'a> Packages<'a> {
+    /// Bytes which have not been processed yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl Iterator for Packages<'_> {
+    type Item = Result<IncomingPackage, Infallible>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = &mut self.bytes;
+        match self.decoder.incoming.resume(|buf| {
+            let count = min(buf.len(), bytes.len());
+            if count == 0 {
+                return Poll::Pending;
+            }
+            buf[..count].copy_from_slice(&bytes[..count]);
+            *bytes = &bytes[count..];
+            Poll::Ready(Ok(count))
+        }) {
+            Poll::Ready(result) => Some(result),
+            Poll::Pending => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::incoming_package::UserAmount;
+    use crate::Error;
+
+    const FREE_FEEDBACK: [u8; 2] = [0x0C, 0x80];
+    const USER_AMOUNT: [u8; 3] = [0x10, 0x80, 0x83];
+
+    #[test]
+    fn test_feed_split_package() {
+        let mut decoder = Decoder::new();
+        assert!(decoder.feed(&USER_AMOUNT[..2]).next().is_none());
+        let mut packages = decoder.feed(&USER_AMOUNT[2..]);
+        assert!(matches!(
+            packages.next(),
+            Some(Ok(IncomingPackage::UserAmount(UserAmount { total_user: 3 })))
+        ));
+        assert!(packages.next().is_none());
+    }
+
+    #[test]
+    fn test_feed_multiple_packages() {
+        let mut decoder = Decoder::new();
+        let bytes = [
+            FREE_FEEDBACK[0],
+            FREE_FEEDBACK[1],
+            USER_AMOUNT[0],
+            USER_AMOUNT[1],
+        ];
+        let mut packages = decoder.feed(&bytes);
+        assert!(matches!(packages.next(), Some(Ok(IncomingPackage::FreeFeedback(_)))));
+        assert_eq!(packages.remaining(), &USER_AMOUNT[..2]);
+        assert!(packages.next().is_none());
+        assert!(packages.remaining().is_empty());
+        assert!(matches!(
+            decoder.feed(&USER_AMOUNT[2..]).next(),
+            Some(Ok(IncomingPackage::UserAmount(_)))
+        ));
+    }
+
+    #[test]
+    fn test_feed_after_invalid_package() {
+        let mut decoder = Decoder::new();
+        let invalid = [0x01, 0x80, 0x80, 0x00, 0x80, 0x80, 0x80, 0x80, 0x80];
+        let mut packages = decoder.feed(&invalid);
+        assert!(matches!(
+            packages.next(),
+            Some(Err(Error::InvalidPackageData {
+                code: 0x01,
+                invalid_index: 2,
+                ..
+            }))
+        ));
+        assert!(packages.next().is_none());
+        assert!(matches!(
+            decoder.feed(&FREE_FEEDBACK).next(),
+            Some(Ok(IncomingPackage::FreeFeedback(_)))
+        ));
+    }
+}
@@ -0,0 +1,119 @@
+use core::pin::Pin;
+use core::task::Poll;
+
+use futures::{future, ready};
+
+use crate::incoming_package::IncomingPackage;
+use crate::legacy::FrameDecoder;
+use crate::outgoing_package::{bytes_from_package, ControlCommand};
+use crate::traits::AsyncReadWrite;
+use crate::{Decoder, Error, Result};
+
+/// Protocol generation spoken by a device
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Protocol {
+    /// Protocol V7.0, see [PulseOximeter](crate::PulseOximeter)
+    V7,
+    /// Legacy 5 byte real time data, see [LegacyPulseOximeter](crate::LegacyPulseOximeter)
+    Legacy,
+}
+
+/// Number of legacy frames which have to be received to detect the legacy protocol
+const LEGACY_FRAMES: usize = 3;
+
+/// Detect the protocol spoken by the device connected to `port`.
+///
+/// Sends `StopRealTimeData` and waits until the device either answers with `FreeFeedback`
+/// (protocol V7.0) or sends legacy real time data. Bytes received during detection are dropped.
+///
+/// The returned future does not complete if the device does not send anything, so it should be
+/// combined with a timeout.
+pub async fn detect_protocol<T: AsyncReadWrite + Unpin>(
+    port: &mut T,
+) -> Result<Protocol, T::Error> {
+    let request = bytes_from_package(ControlCommand::StopRealTimeData);
+    let mut sent = 0;
+    future::poll_fn(|cx| {
+        while sent < request.len() {
+            let slice = &request[sent..];
+            let count = ready!(Pin::new(&mut *port).poll_write(cx, slice))?;
+            if count == 0 {
+                return Err(Error::DeviceWriteZero).into();
+            }
+            if count > slice.len() {
+                return Err(Error::DeviceWriteTooMuch {
+                    requested: slice.len(),
+                    reported: count,
+                })
+                .into();
+            }
+            sent += count;
+        }
+        Pin::new(&mut *port).poll_flush(cx).map_err(Error::from)
+    })
+    .await?;
+
+    let mut decoder = Decoder::new();
+    decoder.set_resynchronize(true);
+    let mut legacy = FrameDecoder::new();
+    let mut legacy_frames = 0;
+    let mut buffer = [0; 16];
+    future::poll_fn(|cx| loop {
+        let count = ready!(Pin::new(&mut *port).poll_read(cx, &mut buffer))?;
+        if count == 0 {
+            return Err(Error::DeviceReadZero).into();
+        }
+        if count > buffer.len() {
+            return Err(Error::DeviceReadTooMuch {
+                requested: buffer.len(),
+                reported: count,
+            })
+            .into();
+        }
+        let bytes = &buffer[..count];
+        for package in decoder.feed(bytes) {
+            if let Ok(IncomingPackage::FreeFeedback(_)) = package {
+                return Poll::Ready(Ok(Protocol::V7));
+            }
+        }
+        legacy_frames += bytes.iter().filter_map(|&byte| legacy.push(byte)).count();
+        if legacy_frames >= LEGACY_FRAMES {
+            return Poll::Ready(Ok(Protocol::Legacy));
+        }
+    })
+    .await
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::incoming_package::FreeFeedback;
+    use crate::legacy::encode;
+    use crate::mock::{real_time_data, MockPort};
+
+    #[test]
+    fn test_detect_v7() {
+        let mut port = MockPort::new(&[
+            real_time_data(),
+            IncomingPackage::FreeFeedback(FreeFeedback {}),
+        ]);
+        assert_eq!(block_on(detect_protocol(&mut port)).unwrap(), Protocol::V7);
+    }
+
+    #[test]
+    fn test_detect_legacy() {
+        let data = match real_time_data() {
+            IncomingPackage::RealTimeData(data) => data,
+            _ => unreachable!(),
+        };
+        let bytes = (0..LEGACY_FRAMES).flat_map(|_| encode(&data)).collect::<Vec<_>>();
+        let mut port = MockPort::from_bytes(&bytes);
+        assert_eq!(block_on(detect_protocol(&mut port)).unwrap(), Protocol::Legacy);
+    }
+}
@@ -0,0 +1,144 @@
+#[cfg(feature = "std")]
+extern crate std;
+
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll};
+
+use embedded_io_async::{Read, Write};
+
+use crate::traits::AsyncReadWrite;
+
+/// Adapter which implements [AsyncReadWrite] for [embedded_io_async] ports, e.g. a UART of a
+/// microcontroller, created by [EmbeddedIo::from_cancel_safe()].
+///
+/// # Cancel safety
+///
+/// The port must be cancel safe: [Read::read()], [Write::write()] and [Write::flush()] have to be
+/// side-effect-free if their future is dropped before it completed. Every poll of the adapter
+/// creates a new future and drops it again if it is not ready, because the futures borrow the
+/// buffer of the caller and cannot be stored without allocating. A port which e.g. already
+/// consumed bytes from its receive buffer when its read future is dropped loses these bytes, which
+/// is the case for many DMA or interrupt driven UART drivers. Buffered drivers which only copy
+/// bytes that were already received (e.g. ring buffer based ones) are usually cancel safe.
+///
+/// Errors of the port are reported as [Error::DeviceIOError](crate::Error::DeviceIOError). If the
+/// `std` feature is enabled, they are converted to [std::io::Error] based on their
+/// [ErrorKind](embedded_io_async::ErrorKind).
+pub struct EmbeddedIo<T>(T);
+
+impl<T> EmbeddedIo<T> {
+    /// Wrap `port`, whose futures must be cancel safe.
+    ///
+    /// The caller is responsible for the port fulfilling the requirements described in
+    /// [EmbeddedIo], a port which is not cancel safe loses data.
+    pub fn from_cancel_safe(port: T) -> Self {
+        Self(port)
+    }
+
+    /// The wrapped port.
+    pub fn port(&mut self) -> &mut T {
+        &mut self.0
+    }
+
+    /// Unwrap the port.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Read + Write + Unpin> AsyncReadWrite for EmbeddedIo<T> {
+    #[cfg(feature = "std")]
+    type Error = std::io::Error;
+    #[cfg(not(feature = "std"))]
+    type Error = T::Error;
+
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Self::Error>> {
+        pin!(self.get_mut().0.read(buf)).poll(cx).map_err(into_error::<T>)
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Self::Error>> {
+        pin!(self.get_mut().0.write(buf)).poll(cx).map_err(into_error::<T>)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        pin!(self.get_mut().0.flush()).poll(cx).map_err(into_error::<T>)
+    }
+}
+
+#[cfg(feature = "std")]
+fn into_error<T: embedded_io_async::ErrorType>(error: T::Error) -> std::io::Error {
+    use embedded_io_async::Error;
+    std::io::Error::from(std::io::ErrorKind::from(error.kind()))
+}
+
+#[cfg(not(feature = "std"))]
+fn into_error<T: embedded_io_async::ErrorType>(error: T::Error) -> T::Error {
+    error
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::vec::Vec;
+
+    use embedded_io_async::{ErrorKind, ErrorType};
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::incoming_package::{IncomingPackage, UserAmount};
+    use crate::{Error, PulseOximeter};
+
+    /// Port which answers every read with the prepared bytes, or fails once they are used up
+    struct Uart {
+        responses: Vec<u8>,
+        written: Vec<u8>,
+    }
+
+    impl ErrorType for Uart {
+        type Error = ErrorKind;
+    }
+
+    impl Read for Uart {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+            if self.responses.is_empty() {
+                return Err(ErrorKind::TimedOut);
+            }
+            let count = buf.len().min(self.responses.len());
+            buf[..count].copy_from_slice(&self.responses[..count]);
+            self.responses.drain(..count);
+            Ok(count)
+        }
+    }
+
+    impl Write for Uart {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn test_embedded_io() {
+        let package = IncomingPackage::UserAmount(UserAmount { total_user: 3 });
+        let mut device = PulseOximeter::new(EmbeddedIo::from_cancel_safe(Uart {
+            responses: package.to_bytes().to_vec(),
+            written: Vec::new(),
+        }));
+        assert_eq!(block_on(device.user_amount()).unwrap(), 3);
+        assert_eq!(device.port.0.written.len(), 9);
+        match block_on(device.receive_package()) {
+            Err(Error::DeviceIOError { source }) => {
+                assert_eq!(source.kind(), std::io::ErrorKind::TimedOut)
+            }
+            result => panic!("unexpected result {result:?}"),
+        }
+    }
+}
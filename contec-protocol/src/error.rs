@@ -1,6 +1,10 @@
-use core::fmt::Debug;
+use core::fmt::{self, Debug, Display, Formatter};
 
-use snafu::{AsErrorSource, Snafu};
+#[cfg(feature = "std")]
+use snafu::AsErrorSource;
+use snafu::Snafu;
+
+use crate::incoming_package::{CommandFeedback, DecodeContext, FeedbackCode};
 
 /// A specialized `Error` type that provides device communication error information.
 #[derive(Snafu, Debug)]
@@ -46,31 +50,142 @@ pub enum Error<#[cfg(feature = "std")] E: AsErrorSource, #[cfg(not(feature = "st
 
     /// invalid package
     #[snafu(display(
-        "synchronization bit of byte '{:02X?}' at index '{}' must be set. Raw package: '{:02X?}' {:02X?}",
-        bytes[*invalid_index],
+        "synchronization bit of byte at index '{}' of package {:#04X} must be set{}",
         invalid_index,
         code,
-        &bytes[..*length]
+        OptionalContext(", ", context)
     ))]
     InvalidPackageData {
         /// package type code
         code: u8,
-        /// package bytes (incuding high byte)
-        bytes: [u8; 8],
-        /// package length
-        length: usize,
-        /// index of first invalid byte
+        /// index of first invalid byte (the high byte has index 0)
         invalid_index: usize,
+        /// position and bytes around the invalid byte, `None` if the byte is no longer known
+        context: Option<DecodeContext>,
     },
 
     /// unexpected package type code encountered
-    #[snafu(display("got unknown package type code {code:#04X}"))]
+    #[snafu(display(
+        "got unknown package type code {code:#04X}{}",
+        OptionalContext(" ", context)
+    ))]
     UnknownTypeCode {
         /// unknown type code
         code: u8,
+        /// position and bytes around the type code, `None` if the type code is no longer known
+        context: Option<DecodeContext>,
+    },
+
+    /// device does not support a command
+    #[snafu(display("command {command:#04X} is not supported by the device"))]
+    NotSupported {
+        /// command code
+        command: u8,
+    },
+
+    /// device rejected a command because it is recording
+    #[snafu(display("device is recording and rejected command {command:#04X}"))]
+    DeviceRecording {
+        /// command code
+        command: u8,
+    },
+
+    /// device failed to delete storage data
+    #[snafu(display("device failed to delete the storage data"))]
+    DeleteFailed,
+
+    /// device answered a command with a non-zero command feedback
+    #[snafu(display(
+        "device rejected command {:#04X}: {}",
+        feedback.command,
+        feedback.message()
+    ))]
+    CommandFailed {
+        /// command feedback sent by the device
+        feedback: CommandFeedback,
+    },
+
+    /// date to set on the device has an invalid month
+    #[snafu(display("invalid month {month}"))]
+    InvalidMonth {
+        /// month, which is not in `1..=12`
+        month: u8,
+    },
+
+    /// storage data package does not match the storage data identifiers
+    #[snafu(display(
+        "got storage data package {code:#04X}, but the storage data identifiers announced data {} \
+         PI",
+        if *with_pi { "with" } else { "without" }
+    ))]
+    StorageFormatMismatch {
+        /// package type code
+        code: u8,
+        /// whether the identifiers announced data with PI
+        with_pi: bool,
+    },
+
+    /// outgoing queue is full
+    #[snafu(display("outgoing queue is full"))]
+    QueueFull,
+
+    /// device did not respond in time
+    #[snafu(display("device did not respond in time"))]
+    Timeout,
+
+    /// serialized state machine is invalid
+    #[snafu(display("invalid serialized state machine: {reason}"))]
+    InvalidState {
+        /// reason why the state was rejected
+        reason: &'static str,
+    },
+
+    /// unexpected control command code encountered
+    #[snafu(display("got unknown control command code {code:#04X}"))]
+    UnknownCommandCode {
+        /// unknown command code
+        code: u8,
     },
 }
 
+impl<#[cfg(feature = "std")] E: AsErrorSource, #[cfg(not(feature = "std"))] E> Error<E> {
+    /// Position and bytes around the failure, if this is a decode error
+    pub fn decode_context(&self) -> Option<&DecodeContext> {
+        match self {
+            Error::InvalidPackageData { context, .. } | Error::UnknownTypeCode { context, .. } => {
+                context.as_ref()
+            }
+            _ => None,
+        }
+    }
+
+    /// Error for a command feedback which reports that a command failed
+    pub(crate) fn from_feedback(feedback: CommandFeedback) -> Self {
+        match feedback.code {
+            FeedbackCode::NotSupported => Error::NotSupported {
+                command: feedback.command,
+            },
+            FeedbackCode::Recording => Error::DeviceRecording {
+                command: feedback.command,
+            },
+            FeedbackCode::DeleteFailed => Error::DeleteFailed,
+            _ => Error::CommandFailed { feedback },
+        }
+    }
+}
+
+/// Displays a decode context after a separator, or nothing without context
+struct OptionalContext<'a>(&'static str, &'a Option<DecodeContext>);
+
+impl Display for OptionalContext<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            Some(context) => write!(f, "{}{}", self.0, context),
+            None => Ok(()),
+        }
+    }
+}
+
 #[cfg(not(feature = "std"))]
 impl<E> From<E> for Error<E> {
     fn from(source: E) -> Self {
@@ -1,13 +1,15 @@
 //! Packages sent by the device.
 
+use core::cmp::min;
 use core::fmt;
 use core::fmt::{Debug, Formatter};
+use core::ops::Deref;
 use core::task::Poll;
 
 use futures::ready;
 
-use crate::bit_ops::{get_bit, get_bit_range};
-use crate::encoding::decode_high_byte;
+use crate::bit_ops::{get_bit, get_bit_range, set_bit, set_bit_range};
+use crate::encoding::{decode_high_byte, encode_high_byte};
 use crate::Error;
 
 macro_rules! incoming_packages {
@@ -17,23 +19,60 @@ macro_rules! incoming_packages {
             $code:literal => |$bytes:ident: [u8; $length:literal]| $(#[$outer2:meta])* $name:ident {
                 $(
                     $(#[$field_meta:meta])*
-                    $field_vis:vis $field_name:ident: $field_type:ty = $field_const:expr
+                    $field_vis:vis $field_name:ident: $field_type:ty =
+                        $field_const:expr => $field_encode:expr
                 ),*$(,)?
             }
         ),*$(,)?
     ) => {
         /// A Package sent by the device.
         #[derive(Debug)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub enum IncomingPackage {
             $(
                 $(#[$outer])*
                 $name($name),
             )*
+            /// Package registered with [CustomPackages]
+            Custom {
+                /// Type code
+                code: u8,
+                /// Data bytes, without the high byte
+                bytes: CustomBytes,
+            },
+        }
+
+        impl IncomingPackage {
+            /// Type code of the package
+            pub fn code(&self) -> u8 {
+                match self {
+                    $(IncomingPackage::$name(_) => $code,)*
+                    IncomingPackage::Custom { code, .. } => *code,
+                }
+            }
+
+            /// Gives the byte representation of the package, as sent by the device
+            pub fn to_bytes(&self) -> PackageBytes {
+                match self {
+                    $(IncomingPackage::$name(package) => PackageBytes::new(&package.to_bytes()),)*
+                    IncomingPackage::Custom { code, bytes } => {
+                        let mut data = [0; MAX_CUSTOM_LENGTH];
+                        data[..bytes.len()].copy_from_slice(bytes);
+                        let (high_byte, data) = encode_high_byte(data);
+                        let mut buffer = [0; MAX_CUSTOM_LENGTH + 2];
+                        buffer[0] = *code;
+                        buffer[1] = high_byte;
+                        buffer[2..].copy_from_slice(&data);
+                        PackageBytes::new(&buffer[..bytes.len() + 2])
+                    }
+                }
+            }
         }
 
         $(
             $(#[$outer])*
             $(#[$outer2])*
+            #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
             pub struct $name {
                 $(
                     $(#[$field_meta])*
@@ -48,12 +87,41 @@ macro_rules! incoming_packages {
                         $($field_name: $field_const,)*
                     }
                 }
+
+                /// Gives the byte representation of the package, as sent by the device
+                pub fn to_bytes(&self) -> [u8; $length + 2] {
+                    let (high_byte, data) = encode_high_byte(self.data_bytes());
+                    let mut buffer = [0; $length + 2];
+                    buffer[0] = $code;
+                    buffer[1] = high_byte;
+                    buffer[2..].copy_from_slice(&data);
+                    buffer
+                }
+
+                /// Gives the data bytes of the package (without high byte)
+                #[allow(unused_mut)]
+                fn data_bytes(&self) -> [u8; $length] {
+                    let mut $bytes = [0; $length];
+                    $(
+                        let $field_name = self.$field_name;
+                        $field_encode;
+                    )*
+                    $bytes
+                }
             }
         )*
 
-        /// State machine which handles incoming packages
-        pub enum IncomingStateMachine {
-            /// Current status: nothing read
+        /// Length of the longest package (including high byte)
+        const MAX_PACKAGE_LENGTH: usize = max_length(&[$($length + 1),*]);
+
+        /// Whether `code` is the type code of a package known to this library
+        const fn is_known_code(code: u8) -> bool {
+            matches!(code, $($code)|*)
+        }
+
+        /// Package which is currently being received
+        enum ReceiveState {
+            /// Nothing read
             None,
             $(
                 $(#[$outer])*
@@ -64,16 +132,145 @@ macro_rules! incoming_packages {
                     received_bytes: usize
                 },
             )*
+            /// Package registered with [CustomPackages]
+            Custom {
+                /// type code
+                code: u8,
+                /// number of data bytes
+                length: usize,
+                /// byte buffer
+                buffer: [u8; MAX_CUSTOM_LENGTH + 1],
+                /// byte count
+                received_bytes: usize
+            },
+        }
+
+        impl ReceiveState {
+            /// Type code, received bytes and byte count of the package being received
+            fn package(&self) -> Option<(u8, &[u8], usize)> {
+                match self {
+                    ReceiveState::None => None,
+                    $(
+                        ReceiveState::$name { buffer, received_bytes } => {
+                            Some(($code, buffer, *received_bytes))
+                        }
+                    )*
+                    ReceiveState::Custom { code, length, buffer, received_bytes } => {
+                        Some((*code, &buffer[..*length + 1], *received_bytes))
+                    }
+                }
+            }
+
+            /// Resume receiving a package with type `code`, returns `None` if `code` is unknown
+            /// or `received_bytes` is not valid for the package
+            fn from_package(
+                code: u8,
+                bytes: &[u8],
+                received_bytes: usize,
+                custom: &CustomPackages,
+            ) -> Option<Self> {
+                match code {
+                    $(
+                        $code if received_bytes <= $length => {
+                            let mut buffer = [0; ($length + 1)];
+                            buffer.copy_from_slice(&bytes[..($length + 1)]);
+                            Some(ReceiveState::$name { buffer, received_bytes })
+                        }
+                    )*
+                    code => {
+                        let length = custom.length(code).filter(|length| received_bytes <= *length)?;
+                        let mut buffer = [0; MAX_CUSTOM_LENGTH + 1];
+                        buffer[..length + 1].copy_from_slice(&bytes[..length + 1]);
+                        Some(ReceiveState::Custom { code, length, buffer, received_bytes })
+                    }
+                }
+            }
+        }
+
+        /// State machine which handles incoming packages
+        pub struct IncomingStateMachine {
+            /// Package which is currently being received
+            state: ReceiveState,
+            /// Bytes which were read ahead or have to be processed again after resynchronization
+            pending: ReadBuffer,
+            /// Whether invalid bytes are skipped instead of returning an error
+            resynchronize: bool,
+            /// Total number of skipped bytes
+            skipped_bytes: usize,
+            /// Total number of decoded packages
+            packages: u64,
+            /// Packages which are decoded as [IncomingPackage::Custom]
+            custom: CustomPackages,
         }
 
         impl IncomingStateMachine {
+            /// Create a new state machine, which has not received any bytes yet
+            ///
+            /// The state machine reads as many bytes as are available (up to an internal buffer
+            /// size) and decodes the following packages from these bytes, without calling `read`
+            /// again.
+            pub const fn new() -> Self {
+                Self {
+                    state: ReceiveState::None,
+                    pending: ReadBuffer::new(true),
+                    resynchronize: false,
+                    skipped_bytes: 0,
+                    packages: 0,
+                    custom: CustomPackages::new(),
+                }
+            }
+
+            /// Create a new state machine, which never reads beyond the current package
+            pub(crate) const fn without_read_ahead() -> Self {
+                Self {
+                    state: ReceiveState::None,
+                    pending: ReadBuffer::new(false),
+                    resynchronize: false,
+                    skipped_bytes: 0,
+                    packages: 0,
+                    custom: CustomPackages::new(),
+                }
+            }
+
+            /// Enable or disable resynchronization
+            ///
+            /// By default, the state machine returns [Error::UnknownTypeCode] or
+            /// [Error::InvalidPackageData] on the first invalid byte. With resynchronization
+            /// enabled, invalid bytes are dropped instead, until the next valid type code (high
+            /// bit clear) is encountered. Use [IncomingStateMachine::skipped_bytes()] to find out
+            /// how many bytes were dropped.
+            pub fn set_resynchronize(&mut self, resynchronize: bool) {
+                self.resynchronize = resynchronize;
+            }
+
+            /// Total number of bytes which were dropped during resynchronization
+            pub fn skipped_bytes(&self) -> usize {
+                self.skipped_bytes
+            }
+
+            /// Decode the packages of `custom` as [IncomingPackage::Custom], instead of failing
+            /// with [Error::UnknownTypeCode]
+            pub fn set_custom_packages(&mut self, custom: CustomPackages) {
+                self.custom = custom;
+            }
+
+            /// Total number of bytes which were processed
+            pub fn offset(&self) -> u64 {
+                self.pending.consumed
+            }
+
+            /// Total number of packages which were decoded
+            pub fn packages(&self) -> u64 {
+                self.packages
+            }
+
             /// Resumes execution of the state machine
             ///
             /// # Arguments
             ///
             /// * `read` - A function which read new bytes into the provided buffer.
-            /// It must return `Poll::Pending` if no data is available for reading, or
-            /// `Poll::Ready(Ok(num_bytes_read))` otherwise.
+            ///   It must return `Poll::Pending` if no data is available for reading, or
+            ///   `Poll::Ready(Ok(num_bytes_read))` otherwise.
             pub fn resume<
                 #[cfg(feature = "std")] E: snafu::AsErrorSource,
                 #[cfg(not(feature = "std"))] E,
@@ -82,243 +279,1005 @@ macro_rules! incoming_packages {
                 mut read: impl FnMut(&mut [u8]) -> Poll<core::result::Result<usize, E>>
             ) -> Poll<$crate::Result<IncomingPackage, E>> {
                 loop {
-                    match self {
-                        IncomingStateMachine::None => {
+                    let Self { state, pending, resynchronize, skipped_bytes, packages, custom } =
+                        self;
+                    match state {
+                        ReceiveState::None => {
                             // Read single byte to identify package
                             let mut code = [0u8];
-                            let count = ready!(read(&mut code))?;
-                            if count == 0 {
-                                return Err(Error::DeviceReadZero).into();
-                            }
-                            if count > 1 {
-                                return Err(
-                                    Error::DeviceReadTooMuch { requested: 1, reported: count }
-                                ).into();
-                            }
+                            ready!(pending.read(&mut read, &mut code))?;
                             match code[0] {
                                 $(
-                                    $code => *self = IncomingStateMachine::$name {
+                                    $code => *state = ReceiveState::$name {
                                         buffer: [0; ($length + 1)],
                                         received_bytes: 0
                                     },
                                 )*
-                                code => return Err(Error::UnknownTypeCode{ code }).into(),
+                                code => match custom.length(code) {
+                                    Some(length) => *state = ReceiveState::Custom {
+                                        code,
+                                        length,
+                                        buffer: [0; MAX_CUSTOM_LENGTH + 1],
+                                        received_bytes: 0
+                                    },
+                                    None if *resynchronize => *skipped_bytes += 1,
+                                    None => {
+                                        let position = pending.consumed - 1;
+                                        return Err(Error::UnknownTypeCode {
+                                            code,
+                                            context: pending.context(position, *packages),
+                                        }).into();
+                                    }
+                                }
                             }
                         },
                         $(
-                            IncomingStateMachine::$name {
+                            ReceiveState::$name {
                                 ref mut buffer,
                                 ref mut received_bytes
                             } => {
                                 // Continue reading the required number of bytes for the signaled
                                 // package type
-                                let slice = &mut buffer[*received_bytes..($length + 1)];
-                                let count = ready!(read(slice))?;
-                                if count == 0 {
-                                    return Err(Error::DeviceReadZero).into();
-                                }
-                                if count > slice.len() {
-                                    return Err(Error::DeviceReadTooMuch {
-                                        requested: slice.len(),
-                                        reported: count
-                                    }).into();
-                                }
+                                let start = *received_bytes;
+                                let count = ready!(pending.read(&mut read, &mut buffer[start..]))?;
                                 *received_bytes += count;
+                                if *resynchronize {
+                                    if let Some(index) = buffer[start..*received_bytes]
+                                        .iter()
+                                        .position(|byte| !get_bit(*byte, 7))
+                                    {
+                                        // The type code and all bytes before the invalid byte are
+                                        // dropped, the invalid byte might be the type code of the
+                                        // next package
+                                        let invalid_index = start + index;
+                                        *skipped_bytes += 1 + invalid_index;
+                                        pending.unread(&buffer[invalid_index..*received_bytes]);
+                                        *state = ReceiveState::None;
+                                        continue;
+                                    }
+                                }
                                 if *received_bytes == ($length + 1) {
                                     // Package received completely, decode
                                     let [high_byte, data @ ..] = *buffer;
                                     let decoded = match decode_high_byte((high_byte, data)){
                                         Ok(decoded) => decoded,
                                         Err(invalid_index) => {
-                                            let mut bytes = [0; 8];
-                                            bytes[..$length+1].copy_from_slice(buffer);
+                                            // Drop the package, the next one can still be decoded
+                                            *state = ReceiveState::None;
+                                            let position = pending.consumed - ($length + 1)
+                                                + invalid_index as u64;
                                             return Err(Error::InvalidPackageData {
                                                 code: $code,
-                                                bytes,
-                                                length: $length+1,
-                                                invalid_index
+                                                invalid_index,
+                                                context: pending.context(position, *packages),
                                             }).into();
                                         }
                                     };
                                     let data = $name::from_bytes(decoded);
 
                                     // Reset state machine
-                                    *self = IncomingStateMachine::None;
+                                    *state = ReceiveState::None;
+                                    *packages += 1;
 
                                     return Poll::Ready(Ok(IncomingPackage::$name(data)))
                                 }
                             },
                         )*
+                        ReceiveState::Custom {
+                            code,
+                            length,
+                            ref mut buffer,
+                            ref mut received_bytes
+                        } => {
+                            let (code, length) = (*code, *length);
+                            let start = *received_bytes;
+                            let count =
+                                ready!(pending.read(&mut read, &mut buffer[start..length + 1]))?;
+                            *received_bytes += count;
+                            if *resynchronize {
+                                if let Some(index) = buffer[start..*received_bytes]
+                                    .iter()
+                                    .position(|byte| !get_bit(*byte, 7))
+                                {
+                                    // Same as for the known packages above
+                                    let invalid_index = start + index;
+                                    *skipped_bytes += 1 + invalid_index;
+                                    pending.unread(&buffer[invalid_index..*received_bytes]);
+                                    *state = ReceiveState::None;
+                                    continue;
+                                }
+                            }
+                            if *received_bytes == length + 1 {
+                                // Unused data bytes are padded with valid bytes
+                                let mut data = [0x80; MAX_CUSTOM_LENGTH];
+                                data[..length].copy_from_slice(&buffer[1..length + 1]);
+                                let decoded = match decode_high_byte((buffer[0], data)) {
+                                    Ok(decoded) => decoded,
+                                    Err(invalid_index) => {
+                                        *state = ReceiveState::None;
+                                        let position = pending.consumed - (length as u64 + 1)
+                                            + invalid_index as u64;
+                                        return Err(Error::InvalidPackageData {
+                                            code,
+                                            invalid_index,
+                                            context: pending.context(position, *packages),
+                                        }).into();
+                                    }
+                                };
+
+                                // Reset state machine
+                                *state = ReceiveState::None;
+                                *packages += 1;
+
+                                return Poll::Ready(Ok(IncomingPackage::Custom {
+                                    code,
+                                    bytes: CustomBytes::new(&decoded[..length]),
+                                }));
+                            }
+                        },
                     }
                 }
             }
         }
+
+        impl Default for IncomingStateMachine {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
     };
 }
 
+/// Generates an enum for a code byte with an `Unknown` fallback for unknown codes
+macro_rules! codes {
+    ($(
+        $(#[$meta:meta])*
+        $name:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $code:literal => $variant:ident: $message:literal,
+            )*
+        }
+    )*) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+            #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+            #[non_exhaustive]
+            pub enum $name {
+                $(
+                    $(#[$variant_meta])*
+                    $variant,
+                )*
+                /// Code which is not known to this library
+                Unknown(u8),
+            }
+
+            impl $name {
+                /// Human readable meaning of this code
+                pub fn message(&self) -> &'static str {
+                    match self {
+                        $($name::$variant => $message,)*
+                        $name::Unknown(_) => "Unknown reason",
+                    }
+                }
+            }
+
+            impl From<u8> for $name {
+                fn from(code: u8) -> Self {
+                    match code {
+                        $($code => $name::$variant,)*
+                        code => $name::Unknown(code),
+                    }
+                }
+            }
+
+            impl From<$name> for u8 {
+                fn from(code: $name) -> Self {
+                    match code {
+                        $($name::$variant => $code,)*
+                        $name::Unknown(code) => code,
+                    }
+                }
+            }
+
+            impl fmt::Display for $name {
+                fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                    f.write_str(self.message())
+                }
+            }
+        )*
+    };
+}
+
+/// Returns the largest of the given package lengths
+const fn max_length(lengths: &[usize]) -> usize {
+    let mut max = 0;
+    let mut i = 0;
+    while i < lengths.len() {
+        if lengths[i] > max {
+            max = lengths[i];
+        }
+        i += 1;
+    }
+    max
+}
+
+/// Maximum number of bytes which are read ahead
+const READ_AHEAD_LENGTH: usize = 64;
+
+/// Capacity of [ReadBuffer]
+const READ_BUFFER_LENGTH: usize = READ_AHEAD_LENGTH + MAX_PACKAGE_LENGTH;
+
+/// Number of processed bytes which are kept for [DecodeContext]
+const HISTORY_LENGTH: usize = 16;
+
+/// Maximum number of bytes following an invalid byte in [DecodeContext]
+const CONTEXT_AFTER: usize = 8;
+
+/// Version of the serialized state machine, incremented on every change of the format
+const STATE_VERSION: u8 = 3;
+
+const FLAG_RESYNCHRONIZE: u8 = 1 << 0;
+const FLAG_READ_AHEAD: u8 = 1 << 1;
+const FLAG_RECEIVING: u8 = 1 << 2;
+
+/// Offset of the package being received in the serialized state machine
+const STATE_PACKAGE_OFFSET: usize = 26;
+/// Offset of the pending bytes in the serialized state machine
+const STATE_PENDING_OFFSET: usize = STATE_PACKAGE_OFFSET + 2 + MAX_PACKAGE_LENGTH;
+/// Offset of the history in the serialized state machine
+const STATE_HISTORY_OFFSET: usize = STATE_PENDING_OFFSET + 1 + READ_BUFFER_LENGTH;
+/// Offset of the custom packages in the serialized state machine
+const STATE_CUSTOM_OFFSET: usize = STATE_HISTORY_OFFSET + 1 + HISTORY_LENGTH;
+
+impl IncomingStateMachine {
+    /// Length of the serialized state machine, see [IncomingStateMachine::to_bytes()]
+    pub const SERIALIZED_LENGTH: usize = STATE_CUSTOM_OFFSET + 1 + 2 * MAX_CUSTOM_PACKAGES;
+
+    /// Serializes the state machine, e.g. to persist it in a host which can only store opaque
+    /// byte arrays.
+    ///
+    /// The format starts with a version tag and does not depend on the memory layout, use
+    /// [IncomingStateMachine::try_from_bytes()] to restore the state machine.
+    pub fn to_bytes(&self) -> [u8; Self::SERIALIZED_LENGTH] {
+        let mut bytes = [0; Self::SERIALIZED_LENGTH];
+        bytes[0] = STATE_VERSION;
+        let mut flags = 0;
+        if self.resynchronize {
+            flags |= FLAG_RESYNCHRONIZE;
+        }
+        if self.pending.read_ahead {
+            flags |= FLAG_READ_AHEAD;
+        }
+        bytes[2..10].copy_from_slice(&(self.skipped_bytes as u64).to_le_bytes());
+        bytes[10..18].copy_from_slice(&self.pending.consumed.to_le_bytes());
+        bytes[18..26].copy_from_slice(&self.packages.to_le_bytes());
+        if let Some((code, buffer, received_bytes)) = self.state.package() {
+            flags |= FLAG_RECEIVING;
+            let offset = STATE_PACKAGE_OFFSET;
+            bytes[offset] = code;
+            bytes[offset + 1] = received_bytes as u8;
+            bytes[offset + 2..offset + 2 + buffer.len()].copy_from_slice(buffer);
+        }
+        bytes[1] = flags;
+        let pending = &self.pending.buffer[self.pending.start..self.pending.end];
+        let offset = STATE_PENDING_OFFSET;
+        bytes[offset] = pending.len() as u8;
+        bytes[offset + 1..offset + 1 + pending.len()].copy_from_slice(pending);
+        let offset = STATE_HISTORY_OFFSET;
+        bytes[offset] = self.pending.history_length as u8;
+        for (target, byte) in bytes[offset + 1..].iter_mut().zip(self.pending.history()) {
+            *target = byte;
+        }
+        let offset = STATE_CUSTOM_OFFSET;
+        bytes[offset] = self.custom.length as u8;
+        for (target, (code, length)) in bytes[offset + 1..]
+            .chunks_mut(2)
+            .zip(&self.custom.definitions[..self.custom.length])
+        {
+            target.copy_from_slice(&[*code, *length]);
+        }
+        bytes
+    }
+
+    /// Restores a state machine serialized by [IncomingStateMachine::to_bytes()].
+    ///
+    /// Returns [Error::InvalidState] if the bytes were written by an incompatible version or do
+    /// not describe a valid state.
+    pub fn try_from_bytes(bytes: &[u8]) -> crate::Result<Self, core::convert::Infallible> {
+        let invalid = |reason| Err(Error::InvalidState { reason });
+        if bytes.first() != Some(&STATE_VERSION) {
+            return invalid("unsupported version");
+        }
+        if bytes.len() != Self::SERIALIZED_LENGTH {
+            return invalid("wrong length");
+        }
+        let flags = bytes[1];
+        if flags & !(FLAG_RESYNCHRONIZE | FLAG_READ_AHEAD | FLAG_RECEIVING) != 0 {
+            return invalid("unknown flags");
+        }
+        let read_u64 =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        let offset = STATE_CUSTOM_OFFSET;
+        let mut custom = CustomPackages::new();
+        if bytes[offset] as usize > MAX_CUSTOM_PACKAGES {
+            return invalid("too many custom packages");
+        }
+        for definition in bytes[offset + 1..].chunks(2).take(bytes[offset] as usize) {
+            let (code, length) = (definition[0], definition[1] as usize);
+            if let Some(reason) = custom.check(code, length) {
+                return invalid(reason);
+            }
+            custom = custom.with(code, length);
+        }
+        let (state, received_bytes) = if flags & FLAG_RECEIVING != 0 {
+            let offset = STATE_PACKAGE_OFFSET;
+            let code = bytes[offset];
+            let received_bytes = bytes[offset + 1] as usize;
+            match ReceiveState::from_package(code, &bytes[offset + 2..], received_bytes, &custom) {
+                Some(state) => (state, received_bytes),
+                None => return invalid("invalid package"),
+            }
+        } else {
+            (ReceiveState::None, 0)
+        };
+        let offset = STATE_PENDING_OFFSET;
+        let pending_length = bytes[offset] as usize;
+        // Resynchronization puts the received bytes back into the buffer
+        if pending_length + received_bytes > READ_BUFFER_LENGTH {
+            return invalid("too many pending bytes");
+        }
+        let mut pending = ReadBuffer::new(flags & FLAG_READ_AHEAD != 0);
+        pending.buffer[..pending_length]
+            .copy_from_slice(&bytes[offset + 1..offset + 1 + pending_length]);
+        pending.end = pending_length;
+        let offset = STATE_HISTORY_OFFSET;
+        let history_length = bytes[offset] as usize;
+        if history_length > HISTORY_LENGTH {
+            return invalid("history too long");
+        }
+        // The history is restored by processing its bytes again
+        pending.consumed = match read_u64(10).checked_sub(history_length as u64) {
+            Some(consumed) => consumed,
+            None => return invalid("history longer than the processed bytes"),
+        };
+        pending.record(&bytes[offset + 1..offset + 1 + history_length]);
+        Ok(Self {
+            state,
+            pending,
+            resynchronize: flags & FLAG_RESYNCHRONIZE != 0,
+            skipped_bytes: read_u64(2) as usize,
+            packages: read_u64(18),
+            custom,
+        })
+    }
+}
+
+/// Bytes which were already read from the device, but have not been processed yet
+///
+/// Besides the bytes read ahead, there has to be room for the bytes of a package which are put
+/// back during resynchronization. The last processed bytes are kept for [DecodeContext].
+struct ReadBuffer {
+    buffer: [u8; READ_BUFFER_LENGTH],
+    start: usize,
+    end: usize,
+    /// Whether more bytes than requested are read from the device
+    read_ahead: bool,
+    /// Total number of processed bytes, bytes which were put back are not counted
+    consumed: u64,
+    /// Last processed bytes, the byte at stream offset `i` is stored at `i % HISTORY_LENGTH`
+    history: [u8; HISTORY_LENGTH],
+    /// Number of valid bytes in `history`
+    history_length: usize,
+}
+
+impl ReadBuffer {
+    const fn new(read_ahead: bool) -> Self {
+        Self {
+            buffer: [0; READ_BUFFER_LENGTH],
+            start: 0,
+            end: 0,
+            read_ahead,
+            consumed: 0,
+            history: [0; HISTORY_LENGTH],
+            history_length: 0,
+        }
+    }
+
+    /// Reads buffered bytes into `buf`, or calls `read` if there are none left
+    fn read<#[cfg(feature = "std")] E: snafu::AsErrorSource, #[cfg(not(feature = "std"))] E>(
+        &mut self,
+        read: &mut impl FnMut(&mut [u8]) -> Poll<core::result::Result<usize, E>>,
+        buf: &mut [u8],
+    ) -> Poll<crate::Result<usize, E>> {
+        if self.start == self.end {
+            if !self.read_ahead {
+                let count = ready!(Self::read_from(read, buf))?;
+                self.record(&buf[..count]);
+                return Poll::Ready(Ok(count));
+            }
+            self.end = ready!(Self::read_from(read, &mut self.buffer[..READ_AHEAD_LENGTH]))?;
+            self.start = 0;
+        }
+        let count = min(buf.len(), self.end - self.start);
+        buf[..count].copy_from_slice(&self.buffer[self.start..self.start + count]);
+        self.start += count;
+        self.record(&buf[..count]);
+        Poll::Ready(Ok(count))
+    }
+
+    /// Calls `read` and checks the reported number of bytes
+    fn read_from<
+        #[cfg(feature = "std")] E: snafu::AsErrorSource,
+        #[cfg(not(feature = "std"))] E,
+    >(
+        read: &mut impl FnMut(&mut [u8]) -> Poll<core::result::Result<usize, E>>,
+        buf: &mut [u8],
+    ) -> Poll<crate::Result<usize, E>> {
+        let count = ready!(read(buf))?;
+        if count == 0 {
+            return Err(Error::DeviceReadZero).into();
+        }
+        if count > buf.len() {
+            return Err(Error::DeviceReadTooMuch {
+                requested: buf.len(),
+                reported: count,
+            })
+            .into();
+        }
+        Poll::Ready(Ok(count))
+    }
+
+    /// Counts `bytes` as processed and appends them to the history
+    fn record(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.history[(self.consumed % HISTORY_LENGTH as u64) as usize] = byte;
+            self.consumed += 1;
+        }
+        self.history_length = min(self.history_length + bytes.len(), HISTORY_LENGTH);
+    }
+
+    /// The last processed bytes, oldest first
+    fn history(&self) -> impl Iterator<Item = u8> + '_ {
+        let first = self.consumed - self.history_length as u64;
+        (first..self.consumed).map(|offset| self.history[(offset % HISTORY_LENGTH as u64) as usize])
+    }
+
+    /// Context of the invalid byte at stream offset `offset`, which must have been processed
+    ///
+    /// Returns `None` if the byte is older than the retained history.
+    fn context(&self, offset: u64, packages: u64) -> Option<DecodeContext> {
+        let mut bytes = [0; HISTORY_LENGTH + CONTEXT_AFTER];
+        let mut length = 0;
+        let pending = &self.buffer[self.start..self.end];
+        for byte in self.history().chain(pending.iter().copied().take(CONTEXT_AFTER)) {
+            bytes[length] = byte;
+            length += 1;
+        }
+        let first = self.consumed - self.history_length as u64;
+        let position = offset.checked_sub(first)? as usize;
+        (position < self.history_length)
+            .then(|| DecodeContext::new(&bytes[..length], position, offset, packages))
+    }
+
+    /// Puts `bytes` in front of the remaining buffered bytes
+    fn unread(&mut self, bytes: &[u8]) {
+        // The bytes are processed again
+        self.consumed -= bytes.len() as u64;
+        self.history_length = self.history_length.saturating_sub(bytes.len());
+        let remaining = self.end - self.start;
+        debug_assert!(bytes.len() + remaining <= self.buffer.len());
+        self.buffer.copy_within(self.start..self.end, bytes.len());
+        self.buffer[..bytes.len()].copy_from_slice(bytes);
+        self.start = 0;
+        self.end = bytes.len() + remaining;
+    }
+}
+
+/// Bytes around an invalid byte in the incoming byte stream
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DecodeContext {
+    /// Stream offset of the invalid byte, i.e. the number of bytes processed before it
+    pub offset: u64,
+    /// Number of packages decoded before the invalid byte
+    pub packages: u64,
+    buffer: [u8; HISTORY_LENGTH + CONTEXT_AFTER],
+    length: usize,
+    position: usize,
+}
+
+impl DecodeContext {
+    /// Create a context for the invalid byte `bytes[position]`
+    pub(crate) fn new(bytes: &[u8], position: usize, offset: u64, packages: u64) -> Self {
+        let mut buffer = [0; HISTORY_LENGTH + CONTEXT_AFTER];
+        let length = min(bytes.len(), buffer.len());
+        buffer[..length].copy_from_slice(&bytes[..length]);
+        Self {
+            offset,
+            packages,
+            buffer,
+            length,
+            position,
+        }
+    }
+
+    /// Bytes around the invalid byte, including the bytes before it in the same package
+    pub fn bytes(&self) -> &[u8] {
+        &self.buffer[..self.length]
+    }
+
+    /// Index of the invalid byte in [DecodeContext::bytes()]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Stream offset of the first byte of [DecodeContext::bytes()]
+    pub fn start_offset(&self) -> u64 {
+        self.offset - self.position as u64
+    }
+}
+
+impl fmt::Display for DecodeContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "at byte offset {} after {} packages, bytes: {:02X?}",
+            self.offset,
+            self.packages,
+            self.bytes()
+        )
+    }
+}
+
+/// Byte representation of an [IncomingPackage], including type code and high byte
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PackageBytes {
+    buffer: [u8; MAX_PACKAGE_LENGTH + 1],
+    length: usize,
+}
+
+impl PackageBytes {
+    fn new(bytes: &[u8]) -> Self {
+        let mut buffer = [0; MAX_PACKAGE_LENGTH + 1];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        Self {
+            buffer,
+            length: bytes.len(),
+        }
+    }
+}
+
+impl Deref for PackageBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer[..self.length]
+    }
+}
+
+impl AsRef<[u8]> for PackageBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Maximum number of data bytes of a custom package, limited by the bits of the high byte
+pub const MAX_CUSTOM_LENGTH: usize = 7;
+
+/// Maximum number of packages in [CustomPackages]
+pub const MAX_CUSTOM_PACKAGES: usize = 8;
+
+// Custom packages must fit into the buffers of the known packages
+const _: () = assert!(MAX_CUSTOM_LENGTH < MAX_PACKAGE_LENGTH);
+
+/// Definitions of vendor-specific packages, which are not known to this library.
+///
+/// Some devices send additional package types. Registering their type code and number of data
+/// bytes (without the high byte) lets the decoder return them as [IncomingPackage::Custom]
+/// instead of failing with [Error::UnknownTypeCode]. The definitions can be created at compile
+/// time:
+///
+/// ```
+/// # use contec_protocol::incoming_package::{CustomPackages, IncomingStateMachine};
+/// const CUSTOM: CustomPackages = CustomPackages::new().with(0x20, 4).with(0x21, 7);
+///
+/// let mut machine = IncomingStateMachine::new();
+/// machine.set_custom_packages(CUSTOM);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CustomPackages {
+    /// Type code and number of data bytes of each package
+    definitions: [(u8, u8); MAX_CUSTOM_PACKAGES],
+    /// Number of valid entries in `definitions`
+    length: usize,
+}
+
+impl CustomPackages {
+    /// Create an empty set of definitions.
+    pub const fn new() -> Self {
+        Self {
+            definitions: [(0, 0); MAX_CUSTOM_PACKAGES],
+            length: 0,
+        }
+    }
+
+    /// Add a package with type `code` and `length` data bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the definition is invalid: the high bit of `code` is set, `code` is known to this
+    /// library or already defined, `length` exceeds [MAX_CUSTOM_LENGTH] or there are already
+    /// [MAX_CUSTOM_PACKAGES] definitions.
+    pub const fn with(mut self, code: u8, length: usize) -> Self {
+        if let Some(reason) = self.check(code, length) {
+            panic!("{}", reason);
+        }
+        self.definitions[self.length] = (code, length as u8);
+        self.length += 1;
+        self
+    }
+
+    /// Reason why the definition of a package can not be added
+    const fn check(&self, code: u8, length: usize) -> Option<&'static str> {
+        if code & 0x80 != 0 {
+            return Some("high bit of custom package code is set");
+        }
+        if is_known_code(code) {
+            return Some("custom package code is already known");
+        }
+        if length > MAX_CUSTOM_LENGTH {
+            return Some("custom package is too long");
+        }
+        if self.length == MAX_CUSTOM_PACKAGES {
+            return Some("too many custom packages");
+        }
+        let mut i = 0;
+        while i < self.length {
+            if self.definitions[i].0 == code {
+                return Some("custom package code is defined twice");
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /// Number of data bytes of the package with type `code`, if it is defined
+    pub fn length(&self, code: u8) -> Option<usize> {
+        self.definitions[..self.length]
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, length)| *length as usize)
+    }
+}
+
+impl Default for CustomPackages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Data bytes of an [IncomingPackage::Custom]
+///
+/// Serialized as a byte sequence of at most [MAX_CUSTOM_LENGTH] bytes.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct CustomBytes {
+    buffer: [u8; MAX_CUSTOM_LENGTH],
+    length: usize,
+}
+
+impl CustomBytes {
+    /// Create the data bytes of a custom package.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes` is longer than [MAX_CUSTOM_LENGTH].
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut buffer = [0; MAX_CUSTOM_LENGTH];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        Self {
+            buffer,
+            length: bytes.len(),
+        }
+    }
+}
+
+impl Debug for CustomBytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl Deref for CustomBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer[..self.length]
+    }
+}
+
+impl AsRef<[u8]> for CustomBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for CustomBytes {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for CustomBytes {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error, SeqAccess, Visitor};
+
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = CustomBytes;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                write!(f, "at most {MAX_CUSTOM_LENGTH} bytes")
+            }
+
+            fn visit_bytes<E: Error>(self, bytes: &[u8]) -> Result<CustomBytes, E> {
+                if bytes.len() > MAX_CUSTOM_LENGTH {
+                    return Err(E::invalid_length(bytes.len(), &self));
+                }
+                Ok(CustomBytes::new(bytes))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<CustomBytes, A::Error> {
+                let mut buffer = [0; MAX_CUSTOM_LENGTH];
+                let mut length = 0;
+                while let Some(byte) = seq.next_element()? {
+                    if length == MAX_CUSTOM_LENGTH {
+                        return Err(A::Error::invalid_length(length + 1, &self));
+                    }
+                    buffer[length] = byte;
+                    length += 1;
+                }
+                Ok(CustomBytes { buffer, length })
+            }
+        }
+
+        deserializer.deserialize_bytes(BytesVisitor)
+    }
+}
+
 incoming_packages! {
     /// Real time data
     0x01 => |bytes: [u8; 7]| #[derive(Debug, Copy, Clone)] RealTimeData {
         /// Signal strength
-        pub signal_strength: u8 = get_bit_range(bytes[0], 0..=3),
+        pub signal_strength: u8 =
+            get_bit_range(bytes[0], 0..=3) => set_bit_range(&mut bytes[0], 0..=3, signal_strength),
         /// Searching time too long
-        pub searching_time_too_long: bool = get_bit(bytes[0], 4),
+        pub searching_time_too_long: bool =
+            get_bit(bytes[0], 4) => set_bit(&mut bytes[0], 4, searching_time_too_long),
         /// Low SpO2
-        pub low_spo2: bool = get_bit(bytes[0], 5),
+        pub low_spo2: bool = get_bit(bytes[0], 5) => set_bit(&mut bytes[0], 5, low_spo2),
         /// Pulse beep
-        pub pulse_beep: bool = get_bit(bytes[0], 6),
+        pub pulse_beep: bool = get_bit(bytes[0], 6) => set_bit(&mut bytes[0], 6, pulse_beep),
         /// Probe errors
-        pub probe_errors: bool = get_bit(bytes[0], 7),
+        pub probe_errors: bool = get_bit(bytes[0], 7) => set_bit(&mut bytes[0], 7, probe_errors),
         /// Pulse waveform
-        pub pulse_waveform: u8 = get_bit_range(bytes[1], 0..=6),
+        pub pulse_waveform: u8 =
+            get_bit_range(bytes[1], 0..=6) => set_bit_range(&mut bytes[1], 0..=6, pulse_waveform),
         /// Searching pulse
-        pub searching_pulse: bool = get_bit(bytes[1], 7),
+        pub searching_pulse: bool =
+            get_bit(bytes[1], 7) => set_bit(&mut bytes[1], 7, searching_pulse),
         /// Bar graph
-        pub bar_graph: u8 = get_bit_range(bytes[2], 0..=3),
+        pub bar_graph: u8 =
+            get_bit_range(bytes[2], 0..=3) => set_bit_range(&mut bytes[2], 0..=3, bar_graph),
         /// PI invalid
-        pub pi_invalid: bool = get_bit(bytes[2], 4),
+        pub pi_invalid: bool = get_bit(bytes[2], 4) => set_bit(&mut bytes[2], 4, pi_invalid),
         /// Pulse rate
-        pub pulse_rate: u8 = bytes[3],
+        pub pulse_rate: u8 = bytes[3] => bytes[3] = pulse_rate,
         /// SpO2
-        pub spo2: u8 = bytes[4],
+        pub spo2: u8 = bytes[4] => bytes[4] = spo2,
         /// PI
-        pub pi: u16 = (bytes[5] as u16) + ((bytes[6] as u16) << 8)
+        pub pi: u16 =
+            (bytes[5] as u16) + ((bytes[6] as u16) << 8) => bytes[5..7].copy_from_slice(&pi.to_le_bytes())
     },
     /// Device identifier
     0x04 => |bytes: [u8; 7]| #[derive(Debug, Copy, Clone)] DeviceIdentifier {
         /// Identifier
-        pub identifier: [u8; 7] = bytes,
+        pub identifier: [u8; 7] = bytes => bytes.copy_from_slice(&identifier),
     },
     /// User Information
     0x05 => |bytes: [u8; 7]| #[derive(Debug, Copy, Clone)] UserInformation {
         /// User Index Number
-        pub user_index: u8 = bytes[0],
+        pub user_index: u8 = bytes[0] => bytes[0] = user_index,
         /// User Information
-        pub user_info: [u8; 6] = [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6]]
+        pub user_info: [u8; 6] =
+            [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6]] => bytes[1..7].copy_from_slice(&user_info)
     },
     /// Storage start time(date)
     0x07 => |bytes: [u8; 6]| #[derive(Debug, Copy, Clone)] StorageStartTimeDate {
         /// User Index Number
-        pub user_index: u8 = bytes[0],
+        pub user_index: u8 = bytes[0] => bytes[0] = user_index,
         /// Storage Segment Number
-        pub storage_segment: u8 = bytes[1],
+        pub storage_segment: u8 = bytes[1] => bytes[1] = storage_segment,
         /// Year
-        pub year: u16 = (bytes[2] as u16) + ((bytes[3] as u16) << 8),
+        pub year: u16 =
+            (bytes[2] as u16) + ((bytes[3] as u16) << 8) => bytes[2..4].copy_from_slice(&year.to_le_bytes()),
         /// Month
-        pub month: u8 = bytes[4],
+        pub month: u8 = bytes[4] => bytes[4] = month,
         /// Day
-        pub day: u8 = bytes[5],
+        pub day: u8 = bytes[5] => bytes[5] = day,
     },
     /// Storage start time(time)
     0x12 => |bytes: [u8; 6]| #[derive(Debug, Copy, Clone)] StorageStartTimeTime {
         /// User Index Number
-        pub user_index: u8 = bytes[0],
+        pub user_index: u8 = bytes[0] => bytes[0] = user_index,
         /// Storage Segment Number
-        pub storage_segment: u8 = bytes[1],
+        pub storage_segment: u8 = bytes[1] => bytes[1] = storage_segment,
         /// Hour
-        pub hour: u8 = bytes[2],
+        pub hour: u8 = bytes[2] => bytes[2] = hour,
         /// Minutes
-        pub minute: u8 = bytes[3],
+        pub minute: u8 = bytes[3] => bytes[3] = minute,
         /// Seconds
-        pub second: u8 = bytes[4],
+        pub second: u8 = bytes[4] => bytes[4] = second,
     },
     /// Storage Data Length
     0x08 => |bytes: [u8; 6]| #[derive(Debug, Copy, Clone)] StorageDataLength {
         /// User Index Number
-        pub user_index: u8 = bytes[0],
+        pub user_index: u8 = bytes[0] => bytes[0] = user_index,
         /// Data Segment Number
-        pub data_segment: u8 = bytes[1],
+        pub data_segment: u8 = bytes[1] => bytes[1] = data_segment,
         /// Data segment length
         pub length: u32 =
-            (bytes[2] as u32) + ((bytes[3] as u32) << 8) + ((bytes[4] as u32) << 16) + ((bytes[5] as u32) << 24),
+            (bytes[2] as u32) + ((bytes[3] as u32) << 8) + ((bytes[4] as u32) << 16) + ((bytes[5] as u32) << 24)
+            => bytes[2..6].copy_from_slice(&length.to_le_bytes()),
     },
     /// Storage Data with PI
     0x09 => |bytes: [u8; 4]| #[derive(Debug, Copy, Clone)] StorageDataWithPI {
         /// SpO2
-        pub spo2: u8 = bytes[0],
+        pub spo2: u8 = bytes[0] => bytes[0] = spo2,
         /// Pulse rate
-        pub pulse_rate: u8 = bytes[1],
+        pub pulse_rate: u8 = bytes[1] => bytes[1] = pulse_rate,
         /// Perfusion Index
-        pub pi: u16 = (bytes[2] as u16) + ((bytes[3] as u16) << 8),
+        pub pi: u16 =
+            (bytes[2] as u16) + ((bytes[3] as u16) << 8) => bytes[2..4].copy_from_slice(&pi.to_le_bytes()),
     },
     /// Storage Data Segment Amount
     0x0A => |bytes: [u8; 2]| #[derive(Debug, Copy, Clone)] StorageDataSegmentAmount {
         /// User Index Number
-        pub user_index: u8 = bytes[0],
+        pub user_index: u8 = bytes[0] => bytes[0] = user_index,
         /// Segment Amount
-        pub segment_amount: u8 = bytes[1],
+        pub segment_amount: u8 = bytes[1] => bytes[1] = segment_amount,
     },
     /// Command Feedback
-    0x0B => |bytes: [u8; 2]| CommandFeedback {
+    0x0B => |bytes: [u8; 2]| #[derive(Copy, Clone)] CommandFeedback {
         /// Command
-        pub command: u8 = bytes[0],
+        pub command: u8 = bytes[0] => bytes[0] = command,
         /// Reason Code
-        pub code: u8 = bytes[1],
+        pub code: FeedbackCode = FeedbackCode::from(bytes[1]) => bytes[1] = code.into(),
     },
     /// Device free feedback
     0x0C => |_bytes: [u8; 0]| #[derive(Debug, Copy, Clone)] FreeFeedback {},
     /// Device disconnect notice
     0x0D => |bytes: [u8; 1]| #[derive(Debug, Copy, Clone)] DisconnectNotice {
         /// Disconnect reason
-        pub reason: u8 = bytes[0],
+        pub reason: DisconnectReason = DisconnectReason::from(bytes[0]) => bytes[0] = reason.into(),
     },
     /// PI Identifiers
     0x0E => |bytes: [u8; 1]| #[derive(Debug, Copy, Clone)] PIIdentifiers {
         /// Whether to support PI in real-time data
-        pub pi_support: u8 = bytes[0],
+        pub pi_support: PISupport = PISupport::from(bytes[0]) => bytes[0] = pi_support.into(),
     },
     /// Storage Data
     0x0F => |bytes: [u8; 6]| #[derive(Debug, Copy, Clone)] StorageData {
         /// SpO2 entry 1
-        pub spo2_1: u8 = bytes[0],
+        pub spo2_1: u8 = bytes[0] => bytes[0] = spo2_1,
         /// Pulse rate entry 1
-        pub pulse_rate_1: u8 = bytes[1],
+        pub pulse_rate_1: u8 = bytes[1] => bytes[1] = pulse_rate_1,
         /// SpO2 entry 2
-        pub spo2_2: u8 = bytes[2],
+        pub spo2_2: u8 = bytes[2] => bytes[2] = spo2_2,
         /// Pulse rate entry 2
-        pub pulse_rate_2: u8 = bytes[3],
+        pub pulse_rate_2: u8 = bytes[3] => bytes[3] = pulse_rate_2,
         /// SpO2 entry 3
-        pub spo2_3: u8 = bytes[4],
+        pub spo2_3: u8 = bytes[4] => bytes[4] = spo2_3,
         /// Pulse rate entry 3
-        pub pulse_rate_3: u8 = bytes[5],
+        pub pulse_rate_3: u8 = bytes[5] => bytes[5] = pulse_rate_3,
     },
     /// User Amount
     0x10 => |bytes: [u8; 1]| #[derive(Debug, Copy, Clone)] UserAmount {
         /// Total User Number
-        pub total_user: u8 = bytes[0],
+        pub total_user: u8 = bytes[0] => bytes[0] = total_user,
     },
     /// Device Notice
     0x11 => |bytes: [u8; 7]| #[derive(Debug, Copy, Clone)] DeviceNotice {
         /// Device Notice Type
-        pub device_notice: u8 = bytes[0],
+        pub device_notice: DeviceNoticeType =
+            DeviceNoticeType::from(bytes[0]) => bytes[0] = device_notice.into(),
         /// Notice Information
-        pub device_info: [u8; 6] = [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6]],
+        pub device_info: [u8; 6] =
+            [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6]] => bytes[1..7].copy_from_slice(&device_info),
     },
     /// Storage Data Identifiers
     0x15 => |bytes: [u8; 7]| #[derive(Debug, Copy, Clone)] StorageDataIdentifiers {
         /// User Index Number
-        pub user_index: u8 = bytes[0],
+        pub user_index: u8 = bytes[0] => bytes[0] = user_index,
         /// Data Segment Number
-        pub data_segment: u8 = bytes[1],
-        /// PI Identifiers
-        pub pi_identifiers: u8 = bytes[2],
+        pub data_segment: u8 = bytes[1] => bytes[1] = data_segment,
+        /// PI Identifiers, see [StorageDataIdentifiers::pi_support()]
+        pub pi_identifiers: u8 = bytes[2] => bytes[2] = pi_identifiers,
         /// Retention
-        pub retention: [u8; 4] = [bytes[3], bytes[4], bytes[5], bytes[6]],
+        ///
+        /// Kept raw, because the protocol (V7.0) does not describe how these bytes are encoded.
+        /// Therefore no sample interval is derived from them either.
+        pub retention: [u8; 4] =
+            [bytes[3], bytes[4], bytes[5], bytes[6]] => bytes[3..7].copy_from_slice(&retention),
     },
 }
 
+codes! {
+    /// Reason code of a [CommandFeedback]
+    FeedbackCode {
+        /// The command was executed
+        0x00 => Completed: "Completed operation",
+        /// The device is shutting down
+        0x01 => ShutdownDevice: "Shutdown device",
+        /// The user of the device is being changed
+        0x02 => ExchangeUsers: "Exchange users",
+        /// The device is recording
+        0x03 => Recording: "Recording",
+        /// The storage data could not be deleted
+        0x04 => DeleteFailed: "Failure to delete the storage data",
+        /// The command is not supported by the device
+        0x05 => NotSupported: "Not supported",
+    }
+
+    /// Reason of a [DisconnectNotice]
+    DisconnectReason {
+        /// The device was switched off
+        0x00 => Shutdown: "Device shut down",
+        /// The battery of the device is low
+        0x01 => LowBattery: "Low battery",
+    }
+
+    /// Type of a [DeviceNotice]
+    DeviceNoticeType {
+        /// The device is shutting down
+        0x01 => ShutdownDevice: "Shutdown device",
+        /// The user of the device was changed
+        0x02 => ExchangeUsers: "Exchange users",
+        /// The device started recording
+        0x03 => Recording: "Recording",
+    }
+
+    /// Answer of [PIIdentifiers]
+    PISupport {
+        /// Real time data does not contain perfusion index
+        0x00 => NotSupported: "PI not supported",
+        /// Real time data contains perfusion index
+        0x01 => Supported: "PI supported",
+    }
+}
+
+impl StorageDataIdentifiers {
+    /// Whether the segment was recorded with perfusion index.
+    ///
+    /// [StorageDataIdentifiers::pi_identifiers] is interpreted like [PIIdentifiers::pi_support].
+    /// The raw field is kept, so that unknown values survive a round trip.
+    pub fn pi_support(&self) -> PISupport {
+        PISupport::from(self.pi_identifiers)
+    }
+
+    /// Whether the segment contains perfusion index, i.e. is sent as [StorageDataWithPI].
+    pub fn with_pi(&self) -> bool {
+        self.pi_support() == PISupport::Supported
+    }
+}
+
 impl CommandFeedback {
     /// Meaning of this device command feedback
     pub fn message(&self) -> &str {
-        match self.code {
-            0x00 => "Completed operation",
-            0x01 => "Shutdown device",
-            0x02 => "Exchange users",
-            0x03 => "Recording",
-            0x04 => "Failure to delete the storage data",
-            0x05 => "Not supported",
-            _ => "Unknown reason",
-        }
+        self.code.message()
     }
 }
 
@@ -326,8 +1285,368 @@ impl Debug for CommandFeedback {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("CommandFeedback")
             .field("command", &format_args!("{:#04X}", self.command))
-            .field("reason_code", &format_args!("{:#04X}", self.code))
+            .field("reason_code", &format_args!("{:#04X}", u8::from(self.code)))
             .field("message", &format_args!("'{}'", self.message()))
+            .field("code", &self.code)
             .finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encoding::encode_high_byte;
+
+    /// Valid real time data package with pulse rate 60 and SpO2 98
+    fn real_time_data() -> [u8; 9] {
+        let (high_byte, data) = encode_high_byte([0, 0, 0, 60, 98, 0, 0]);
+        let mut bytes = [0x01, high_byte, 0, 0, 0, 0, 0, 0, 0];
+        bytes[2..].copy_from_slice(&data);
+        bytes
+    }
+
+    /// Resumes `machine` with bytes from `bytes` until it completes
+    fn receive(
+        machine: &mut IncomingStateMachine,
+        bytes: &mut &[u8],
+    ) -> crate::Result<IncomingPackage, core::convert::Infallible> {
+        match machine.resume(|buf| {
+            let count = min(buf.len(), bytes.len());
+            if count == 0 {
+                return Poll::Pending;
+            }
+            buf[..count].copy_from_slice(&bytes[..count]);
+            *bytes = &bytes[count..];
+            Poll::Ready(Ok(count))
+        }) {
+            Poll::Ready(result) => result,
+            Poll::Pending => panic!("state machine did not complete"),
+        }
+    }
+
+    #[test]
+    fn test_read_ahead() {
+        let mut bytes = [0; 18];
+        bytes[..9].copy_from_slice(&real_time_data());
+        bytes[9..].copy_from_slice(&real_time_data());
+        let mut machine = IncomingStateMachine::new();
+        let mut reads = 0;
+        let mut read = |buf: &mut [u8]| {
+            reads += 1;
+            buf[..bytes.len()].copy_from_slice(&bytes);
+            Poll::Ready(Ok::<_, core::convert::Infallible>(bytes.len()))
+        };
+        for _ in 0..2 {
+            match machine.resume(&mut read) {
+                Poll::Ready(Ok(IncomingPackage::RealTimeData(data))) => assert_eq!(data.spo2, 98),
+                p => panic!("unexpected package {p:?}"),
+            }
+        }
+        assert_eq!(reads, 1);
+    }
+
+    #[test]
+    fn test_serialized_state() {
+        let mut machine = IncomingStateMachine::new();
+        machine.set_resynchronize(true);
+        // Garbage, a complete package and the start of another one
+        let mut bytes = &[0xFF, 0x0C, 0x80, 0x01, 0x80, 0x80][..];
+        assert!(matches!(
+            receive(&mut machine, &mut bytes),
+            Ok(IncomingPackage::FreeFeedback(_))
+        ));
+        assert!(machine
+            .resume(|_| Poll::<Result<_, core::convert::Infallible>>::Pending)
+            .is_pending());
+
+        let serialized = machine.to_bytes();
+        let mut restored = IncomingStateMachine::try_from_bytes(&serialized).unwrap();
+        assert_eq!(restored.to_bytes(), serialized);
+        assert_eq!(restored.skipped_bytes(), 1);
+        let rest = real_time_data();
+        match receive(&mut restored, &mut &rest[3..]) {
+            Ok(IncomingPackage::RealTimeData(data)) => assert_eq!(data.spo2, 98),
+            p => panic!("unexpected package {p:?}"),
+        }
+
+        let mut corrupted = serialized;
+        corrupted[0] = 0;
+        assert!(matches!(
+            IncomingStateMachine::try_from_bytes(&corrupted),
+            Err(Error::InvalidState {
+                reason: "unsupported version"
+            })
+        ));
+        let mut corrupted = serialized;
+        corrupted[STATE_PACKAGE_OFFSET] = 0x42;
+        assert!(IncomingStateMachine::try_from_bytes(&corrupted).is_err());
+        assert!(IncomingStateMachine::try_from_bytes(&serialized[..10]).is_err());
+    }
+
+    #[test]
+    fn test_serialized_state_after_error() {
+        let mut machine = IncomingStateMachine::new();
+        let mut bytes = &[0x01, 0x80, 0x80, 0x00, 0x80, 0x80, 0x80, 0x80, 0x80][..];
+        assert!(matches!(
+            receive(&mut machine, &mut bytes),
+            Err(Error::InvalidPackageData { code: 0x01, .. })
+        ));
+
+        let serialized = machine.to_bytes();
+        let mut restored = IncomingStateMachine::try_from_bytes(&serialized).unwrap();
+        assert_eq!(restored.to_bytes(), serialized);
+        assert!(matches!(
+            receive(&mut restored, &mut &[0x0C, 0x80][..]),
+            Ok(IncomingPackage::FreeFeedback(_))
+        ));
+    }
+
+    #[test]
+    fn test_strict_unknown_type_code() {
+        let mut machine = IncomingStateMachine::new();
+        let result = receive(&mut machine, &mut &[0x42][..]);
+        assert!(matches!(result, Err(Error::UnknownTypeCode { code: 0x42, .. })));
+    }
+
+    #[test]
+    fn test_custom_packages() {
+        let package = IncomingPackage::Custom {
+            code: 0x42,
+            bytes: CustomBytes::new(&[0x01, 0xFF, 0x7F]),
+        };
+        let bytes = package.to_bytes();
+        assert_eq!(*bytes, [0x42, 0x82, 0x81, 0xFF, 0xFF]);
+
+        let mut machine = IncomingStateMachine::new();
+        machine.set_custom_packages(CustomPackages::new().with(0x20, 0).with(0x42, 3));
+        // Stop in the middle of the custom package to serialize the state
+        let mut stream = &bytes[..3];
+        assert!(machine
+            .resume(|buf| {
+                let count = min(buf.len(), stream.len());
+                buf[..count].copy_from_slice(&stream[..count]);
+                stream = &stream[count..];
+                match count {
+                    0 => Poll::<Result<_, core::convert::Infallible>>::Pending,
+                    count => Poll::Ready(Ok(count)),
+                }
+            })
+            .is_pending());
+        let mut machine = IncomingStateMachine::try_from_bytes(&machine.to_bytes()).unwrap();
+        match receive(&mut machine, &mut &bytes[3..]) {
+            Ok(IncomingPackage::Custom { code: 0x42, bytes }) => {
+                assert_eq!(*bytes, [0x01, 0xFF, 0x7F])
+            }
+            p => panic!("unexpected package {p:?}"),
+        }
+        assert!(matches!(
+            receive(&mut machine, &mut &[0x20, 0x80][..]),
+            Ok(IncomingPackage::Custom { code: 0x20, bytes }) if bytes.is_empty()
+        ));
+        assert!(matches!(
+            receive(&mut machine, &mut &[0x43][..]),
+            Err(Error::UnknownTypeCode { code: 0x43, .. })
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "already known")]
+    fn test_custom_package_known_code() {
+        let _ = CustomPackages::new().with(0x01, 7);
+    }
+
+    #[test]
+    fn test_error_context() {
+        let mut bytes = [0; 20];
+        bytes[..9].copy_from_slice(&real_time_data());
+        bytes[9..18].copy_from_slice(&real_time_data());
+        // Clear synchronization bit of the SpO2 byte in the second package
+        bytes[15] = 0x62;
+        let mut machine = IncomingStateMachine::new();
+        let mut stream = &bytes[..];
+        receive(&mut machine, &mut stream).unwrap();
+        let context = match receive(&mut machine, &mut stream) {
+            Err(Error::InvalidPackageData {
+                code: 0x01,
+                invalid_index: 5,
+                context,
+            }) => context,
+            p => panic!("unexpected result {p:?}"),
+        };
+        let context = context.unwrap();
+        assert_eq!(context.offset, 15);
+        assert_eq!(context.packages, 1);
+        assert_eq!(context.start_offset(), 2);
+        assert_eq!(context.bytes(), &bytes[2..]);
+        assert_eq!(context.bytes()[context.position()], 0x62);
+    }
+
+    #[test]
+    fn test_context_outside_history() {
+        let mut pending = ReadBuffer::new(false);
+        let bytes: [u8; HISTORY_LENGTH + 4] = core::array::from_fn(|i| i as u8);
+        pending.record(&bytes);
+        assert!(pending.context(0, 0).is_none());
+        assert!(pending.context(3, 0).is_none());
+        let context = pending.context(4, 0).unwrap();
+        assert_eq!(context.start_offset(), 4);
+        assert_eq!(context.position(), 0);
+        assert_eq!(context.bytes(), &bytes[4..]);
+    }
+
+    #[test]
+    fn test_resynchronize_leading_garbage() {
+        let mut machine = IncomingStateMachine::new();
+        machine.set_resynchronize(true);
+        let mut stream = [0u8; 12];
+        stream[..3].copy_from_slice(&[0xFF, 0x42, 0x85]);
+        stream[3..].copy_from_slice(&real_time_data());
+        let result = receive(&mut machine, &mut &stream[..]);
+        assert!(matches!(
+            result,
+            Ok(IncomingPackage::RealTimeData(RealTimeData {
+                pulse_rate: 60,
+                spo2: 98,
+                ..
+            }))
+        ));
+        assert_eq!(machine.skipped_bytes(), 3);
+    }
+
+    #[test]
+    fn test_resynchronize_truncated_package() {
+        let mut machine = IncomingStateMachine::new();
+        machine.set_resynchronize(true);
+        // Real time data package which is cut off after 4 bytes
+        let mut stream = [0u8; 13];
+        stream[..4].copy_from_slice(&real_time_data()[..4]);
+        stream[4..].copy_from_slice(&real_time_data());
+        let mut bytes = &stream[..];
+        let result = receive(&mut machine, &mut bytes);
+        assert!(matches!(result, Ok(IncomingPackage::RealTimeData(_))));
+        assert_eq!(machine.skipped_bytes(), 4);
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn test_resynchronize_short_package_after_garbage() {
+        let mut machine = IncomingStateMachine::new();
+        machine.set_resynchronize(true);
+        // Broken real time data package, followed by two free feedback packages
+        let stream = [0x01, 0x80, 0x81, 0x0C, 0x80, 0x0C, 0x80];
+        let mut bytes = &stream[..];
+        let result = receive(&mut machine, &mut bytes);
+        assert!(matches!(result, Ok(IncomingPackage::FreeFeedback(_))));
+        let result = receive(&mut machine, &mut bytes);
+        assert!(matches!(result, Ok(IncomingPackage::FreeFeedback(_))));
+        assert_eq!(machine.skipped_bytes(), 3);
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let packages: [&[u8]; 6] = [
+            &real_time_data(),
+            &[0x04, 0x80, 0xD0, 0xCF, 0xB2, 0xB5, 0xB0, 0xDF, 0xC1],
+            &[0x07, 0x80, 0x81, 0x82, 0xE6, 0x87, 0x8A, 0x92],
+            &[0x08, 0x80, 0x80, 0x80, 0x80, 0x90, 0x80, 0x80],
+            &[0x0B, 0x80, 0x80, 0x85],
+            &[0x0C, 0x80],
+        ];
+        for bytes in packages {
+            let package = receive(&mut IncomingStateMachine::new(), &mut &bytes[..]).unwrap();
+            assert_eq!(package.code(), bytes[0]);
+            assert_eq!(&*package.to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let package = RealTimeData {
+            signal_strength: 0x0A,
+            searching_time_too_long: false,
+            low_spo2: true,
+            pulse_beep: false,
+            probe_errors: true,
+            pulse_waveform: 0x55,
+            searching_pulse: true,
+            bar_graph: 0x03,
+            pi_invalid: true,
+            pulse_rate: 140,
+            spo2: 97,
+            pi: 0x0123,
+        };
+        let bytes = package.to_bytes();
+        assert_eq!(bytes, [0x01, 0b10001011, 0xAA, 0xD5, 0x93, 0x8C, 0xE1, 0xA3, 0x81]);
+        let decoded = match receive(&mut IncomingStateMachine::new(), &mut &bytes[..]) {
+            Ok(IncomingPackage::RealTimeData(decoded)) => decoded,
+            p => panic!("unexpected package {p:?}"),
+        };
+        assert_eq!(decoded.to_bytes(), bytes);
+        assert_eq!(decoded.pi, 0x0123);
+    }
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(FeedbackCode::from(0x05), FeedbackCode::NotSupported);
+        assert_eq!(FeedbackCode::from(0x42), FeedbackCode::Unknown(0x42));
+        assert_eq!(u8::from(FeedbackCode::Unknown(0x42)), 0x42);
+        assert_eq!(u8::from(PISupport::Supported), 0x01);
+        let bytes = [0x0B, 0x80, 0x80, 0xC2];
+        match receive(&mut IncomingStateMachine::new(), &mut &bytes[..]) {
+            Ok(IncomingPackage::CommandFeedback(feedback)) => {
+                assert_eq!(feedback.code, FeedbackCode::Unknown(0x42));
+                assert_eq!(feedback.message(), "Unknown reason");
+                assert_eq!(feedback.to_bytes(), bytes);
+            }
+            p => panic!("unexpected package {p:?}"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_command_feedback_debug() {
+        extern crate std;
+        use std::format;
+
+        let feedback = CommandFeedback {
+            command: 0xAE,
+            code: FeedbackCode::NotSupported,
+        };
+        assert_eq!(
+            format!("{feedback:?}"),
+            "CommandFeedback { command: 0xAE, reason_code: 0x05, message: 'Not supported', code: \
+             NotSupported }"
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "serde", feature = "std"))]
+    fn test_serde() {
+        let bytes = [0x0B, 0x80, 0x80, 0x85];
+        let package = receive(&mut IncomingStateMachine::new(), &mut &bytes[..]).unwrap();
+        let json = serde_json::to_string(&package).unwrap();
+        assert_eq!(json, r#"{"CommandFeedback":{"command":0,"code":"NotSupported"}}"#);
+        let decoded: IncomingPackage = serde_json::from_str(&json).unwrap();
+        assert_eq!(&*decoded.to_bytes(), bytes);
+    }
+
+    #[test]
+    #[cfg(all(feature = "serde", feature = "std"))]
+    fn test_serde_custom() {
+        let package = IncomingPackage::Custom {
+            code: 0x42,
+            bytes: CustomBytes::new(&[1, 2, 3]),
+        };
+        let json = serde_json::to_string(&package).unwrap();
+        assert_eq!(json, r#"{"Custom":{"code":66,"bytes":[1,2,3]}}"#);
+        let decoded: IncomingPackage = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.to_bytes(), package.to_bytes());
+
+        let too_long = r#"{"Custom":{"code":66,"bytes":[0,0,0,0,0,0,0,0]}}"#;
+        assert!(serde_json::from_str::<IncomingPackage>(too_long).is_err());
+        // The internal representation is not accepted
+        let raw = r#"{"Custom":{"code":66,"bytes":{"buffer":[0,0,0,0,0,0,0],"length":9}}}"#;
+        assert!(serde_json::from_str::<IncomingPackage>(raw).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+//! Legacy real time data protocol of older Contec devices (e.g. CMS50D+, CMS50E)
+//!
+//! Legacy devices continuously send real time data in 5 byte frames, without being asked. The
+//! first byte of a frame has the synchronization bit (bit 7) set, all other bytes have it cleared.
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use futures::ready;
+
+use crate::bit_ops::{get_bit, get_bit_range, set_bit, set_bit_range};
+use crate::incoming_package::RealTimeData;
+use crate::realtime::RealTimeDevice;
+use crate::traits::AsyncReadWrite;
+use crate::{Error, Result};
+
+/// Length of a legacy real time data frame
+pub const FRAME_LENGTH: usize = 5;
+
+/// Decode a legacy real time data frame.
+///
+/// Legacy devices do not report the perfusion index, so `pi_invalid` is always set.
+pub fn decode(frame: [u8; FRAME_LENGTH]) -> RealTimeData {
+    RealTimeData {
+        signal_strength: get_bit_range(frame[0], 0..=3),
+        searching_time_too_long: get_bit(frame[0], 4),
+        low_spo2: get_bit(frame[0], 5),
+        pulse_beep: get_bit(frame[0], 6),
+        probe_errors: get_bit(frame[2], 4),
+        pulse_waveform: get_bit_range(frame[1], 0..=6),
+        searching_pulse: get_bit(frame[2], 5),
+        bar_graph: get_bit_range(frame[2], 0..=3),
+        pi_invalid: true,
+        pulse_rate: get_bit_range(frame[3], 0..=6) | (get_bit(frame[2], 6) as u8) << 7,
+        spo2: get_bit_range(frame[4], 0..=6),
+        pi: 0,
+    }
+}
+
+/// Encode `data` as legacy real time data frame.
+///
+/// The perfusion index is dropped, values which do not fit into the frame are truncated.
+pub fn encode(data: &RealTimeData) -> [u8; FRAME_LENGTH] {
+    let mut frame = [0x80, 0, 0, 0, 0];
+    set_bit_range(&mut frame[0], 0..=3, data.signal_strength);
+    set_bit(&mut frame[0], 4, data.searching_time_too_long);
+    set_bit(&mut frame[0], 5, data.low_spo2);
+    set_bit(&mut frame[0], 6, data.pulse_beep);
+    set_bit_range(&mut frame[1], 0..=6, data.pulse_waveform);
+    set_bit_range(&mut frame[2], 0..=3, data.bar_graph);
+    set_bit(&mut frame[2], 4, data.probe_errors);
+    set_bit(&mut frame[2], 5, data.searching_pulse);
+    set_bit(&mut frame[2], 6, get_bit(data.pulse_rate, 7));
+    set_bit_range(&mut frame[3], 0..=6, data.pulse_rate);
+    set_bit_range(&mut frame[4], 0..=6, data.spo2);
+    frame
+}
+
+/// Splits a legacy byte stream into frames.
+///
+/// Bytes which do not belong to a complete frame are dropped.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buffer: [u8; FRAME_LENGTH],
+    received: usize,
+    skipped_bytes: usize,
+}
+
+impl FrameDecoder {
+    /// Create a new decoder, which has not received any bytes yet.
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; FRAME_LENGTH],
+            received: 0,
+            skipped_bytes: 0,
+        }
+    }
+
+    /// Total number of bytes which were dropped.
+    pub fn skipped_bytes(&self) -> usize {
+        self.skipped_bytes
+    }
+
+    /// Number of bytes which are missing to complete the current frame
+    pub fn missing(&self) -> usize {
+        FRAME_LENGTH - self.received
+    }
+
+    /// Process a single byte, returns the real time data if `byte` completes a frame.
+    pub fn push(&mut self, byte: u8) -> Option<RealTimeData> {
+        let sync = get_bit(byte, 7);
+        if sync {
+            // Start of a new frame, drop incomplete frame
+            self.skipped_bytes += self.received;
+            self.received = 0;
+        } else if self.received == 0 {
+            // Not synchronized
+            self.skipped_bytes += 1;
+            return None;
+        }
+        self.buffer[self.received] = byte;
+        self.received += 1;
+        if self.received < FRAME_LENGTH {
+            return None;
+        }
+        self.received = 0;
+        Some(decode(self.buffer))
+    }
+}
+
+/// Represents a connection with a pulse oximeter speaking the legacy real time protocol.
+///
+/// Legacy devices only send real time data, use [RealTimeDevice::realtime()] to receive it.
+pub struct LegacyPulseOximeter<T: AsyncReadWrite + Unpin> {
+    port: T,
+    decoder: FrameDecoder,
+}
+
+impl<T: AsyncReadWrite + Unpin> LegacyPulseOximeter<T> {
+    /// Create a pulse oximeter interface, using the given `port` for communication.
+    pub fn new(port: T) -> Self {
+        Self {
+            port,
+            decoder: FrameDecoder::new(),
+        }
+    }
+}
+
+impl<T: AsyncReadWrite + Unpin> RealTimeDevice for LegacyPulseOximeter<T> {
+    type Error = T::Error;
+
+    /// Legacy devices send real time data without being asked
+    fn poll_start_real_time_data(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), T::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Legacy devices do not have to be kept alive
+    fn poll_keep_alive(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), T::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_real_time_data(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<RealTimeData, T::Error>> {
+        let mut buffer = [0; FRAME_LENGTH];
+        loop {
+            // Never read beyond the current frame, so no bytes have to be kept for the next call
+            let buf = &mut buffer[..self.decoder.missing()];
+            let count = ready!(Pin::new(&mut self.port).poll_read(cx, buf))?;
+            if count == 0 {
+                return Err(Error::DeviceReadZero).into();
+            }
+            if count > buf.len() {
+                return Err(Error::DeviceReadTooMuch {
+                    requested: buf.len(),
+                    reported: count,
+                })
+                .into();
+            }
+            for &byte in &buf[..count] {
+                if let Some(data) = self.decoder.push(byte) {
+                    return Poll::Ready(Ok(data));
+                }
+            }
+        }
+    }
+
+    /// Legacy devices can not be stopped
+    fn stop_real_time_data_later(&mut self) {}
+
+    fn poll_real_time_data_stopped(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), T::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn skipped_bytes(&self) -> usize {
+        self.decoder.skipped_bytes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn real_time_data() -> RealTimeData {
+        RealTimeData {
+            signal_strength: 0x0A,
+            searching_time_too_long: false,
+            low_spo2: true,
+            pulse_beep: true,
+            probe_errors: false,
+            pulse_waveform: 0x55,
+            searching_pulse: true,
+            bar_graph: 0x03,
+            pi_invalid: true,
+            pulse_rate: 140,
+            spo2: 97,
+            pi: 0,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let frame = encode(&real_time_data());
+        assert_eq!(frame, [0xEA, 0x55, 0x63, 0x0C, 0x61]);
+        assert_eq!(encode(&decode(frame)), frame);
+        assert_eq!(decode(frame).pulse_rate, 140);
+    }
+
+    #[test]
+    fn test_frame_decoder_resynchronize() {
+        let frame = encode(&real_time_data());
+        let mut decoder = FrameDecoder::new();
+        // Garbage, a truncated frame and a complete frame
+        let stream = [0x12].iter().chain(&frame[..3]).chain(&frame);
+        let frames = stream.filter_map(|&byte| decoder.push(byte)).count();
+        assert_eq!(frames, 1);
+        assert_eq!(decoder.skipped_bytes(), 4);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_realtime() {
+        use futures::executor::block_on;
+        use futures::StreamExt;
+
+        use crate::mock::{MockPort, MockTimer};
+
+        let frame = encode(&real_time_data());
+        let mut bytes = [0; 2 * FRAME_LENGTH + 2];
+        bytes[..2].copy_from_slice(&frame[3..]);
+        bytes[2..7].copy_from_slice(&frame);
+        bytes[7..].copy_from_slice(&frame);
+        let mut device = LegacyPulseOximeter::new(MockPort::from_bytes(&bytes));
+        block_on(async {
+            let mut stream = device.realtime(MockTimer { elapsed: 0 });
+            assert_eq!(stream.next().await.unwrap().unwrap().pulse_rate, 140);
+            assert_eq!(stream.next().await.unwrap().unwrap().spo2, 97);
+            stream.stop().await.unwrap();
+        });
+        assert_eq!(device.skipped_bytes(), 2);
+        assert!(device.port.sent_commands().is_empty());
+    }
+}
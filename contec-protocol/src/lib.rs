@@ -1,20 +1,83 @@
 //! Implements the communication protocol of Contec pulse oximeters (V7.0)
+//!
+//! Older devices, which only send real time data in the legacy 5 byte format, are supported by
+//! [LegacyPulseOximeter]. Use [detect_protocol()] to find out which protocol a device speaks.
 #![no_std]
 #![warn(missing_docs)]
+// Tests compare the results of bit operations with bool literals
+#![cfg_attr(test, allow(clippy::bool_assert_comparison))]
 
 mod bit_ops;
 
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "std")]
+pub use capture::{Capture, Replay};
+
+#[cfg(feature = "std")]
+mod blocking;
+#[cfg(feature = "std")]
+pub use blocking::{
+    BlockingIter, BlockingPort, BlockingPulseOximeter, BlockingSleep, BlockingTimer,
+};
+
 mod error;
 pub use error::Error;
 
+mod date_time;
+pub use date_time::DateTime;
+
+mod decoder;
+pub use decoder::{Decoder, Packages};
+
+mod detect;
+pub use detect::{detect_protocol, Protocol};
+
+#[cfg(feature = "embedded-io-async")]
+mod embedded_io;
+#[cfg(feature = "embedded-io-async")]
+pub use embedded_io::EmbeddedIo;
+
 mod encoding;
 
 pub mod incoming_package;
 
+pub mod legacy;
+pub use legacy::LegacyPulseOximeter;
+
+#[cfg(all(test, feature = "std"))]
+mod mock;
+
 pub mod outgoing_package;
 
 mod pulse_oximeter;
-pub use pulse_oximeter::PulseOximeter;
+pub use pulse_oximeter::{PulseOximeter, OUTGOING_QUEUE_LENGTH};
+
+mod realtime;
+pub use realtime::{RealTimeDataStream, RealTimeDevice};
+
+mod session;
+pub use session::{Mode, Session};
+
+#[cfg(feature = "std")]
+mod split;
+#[cfg(feature = "std")]
+pub use split::{ReceiveHalf, SendHalf};
+
+mod storage;
+pub use storage::{StorageDataStream, StorageSample};
+
+#[cfg(feature = "tokio")]
+mod tokio_io;
+#[cfg(feature = "tokio")]
+pub use tokio_io::TokioIo;
+
+pub mod timer;
+#[cfg(feature = "embassy-time")]
+pub use timer::EmbassyTimer;
+#[cfg(feature = "tokio")]
+pub use timer::TokioTimer;
+pub use timer::{RetryPolicy, Timed, Timer};
 
 mod traits;
 pub use traits::AsyncReadWrite;
@@ -0,0 +1,158 @@
+//! Mock port for tests
+extern crate std;
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use std::io;
+use std::vec::Vec;
+
+use futures::future::{self, Either};
+use futures::io::{AsyncRead, AsyncWrite};
+
+use crate::incoming_package::{IncomingPackage, RealTimeData};
+use crate::outgoing_package::{package_from_bytes, AnyOutgoingPackage};
+use crate::Timer;
+
+/// Port which returns prepared responses and records all written bytes
+pub(crate) struct MockPort {
+    responses: Vec<u8>,
+    written: Vec<u8>,
+    /// Number of written bytes when the port was flushed the last time
+    pub(crate) flushed: usize,
+}
+
+impl MockPort {
+    pub(crate) fn new(responses: &[IncomingPackage]) -> Self {
+        Self {
+            responses: responses.iter().flat_map(|p| p.to_bytes().to_vec()).collect(),
+            written: Vec::new(),
+            flushed: 0,
+        }
+    }
+
+    pub(crate) fn from_bytes(responses: &[u8]) -> Self {
+        Self {
+            responses: responses.to_vec(),
+            written: Vec::new(),
+            flushed: 0,
+        }
+    }
+
+    /// Packages written to the port
+    pub(crate) fn sent_commands(&self) -> Vec<AnyOutgoingPackage> {
+        self.written
+            .chunks(9)
+            .map(|bytes| package_from_bytes(bytes.try_into().unwrap()).unwrap())
+            .collect()
+    }
+}
+
+impl AsyncRead for MockPort {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let count = buf.len().min(self.responses.len());
+        buf[..count].copy_from_slice(&self.responses[..count]);
+        self.responses.drain(..count);
+        Poll::Ready(Ok(count))
+    }
+}
+
+impl AsyncWrite for MockPort {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.written.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.flushed = self.written.len();
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Port which ignores the first `ignored` commands and waits forever after the last response
+pub(crate) struct SilentPort {
+    pub(crate) mock: MockPort,
+    pub(crate) ignored: usize,
+}
+
+impl AsyncRead for SilentPort {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.mock.sent_commands().len() <= self.ignored {
+            return Poll::Pending;
+        }
+        match AsyncRead::poll_read(Pin::new(&mut self.mock), cx, buf) {
+            Poll::Ready(Ok(0)) => Poll::Pending,
+            result => result,
+        }
+    }
+}
+
+impl AsyncWrite for SilentPort {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.mock), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(Pin::new(&mut self.mock), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_close(Pin::new(&mut self.mock), cx)
+    }
+}
+
+/// Timer whose first `elapsed` delays complete immediately, and all others never
+pub(crate) struct MockTimer {
+    pub(crate) elapsed: usize,
+}
+
+impl Timer for MockTimer {
+    type Sleep = Either<future::Ready<()>, future::Pending<()>>;
+
+    fn sleep(&mut self, _duration: Duration) -> Self::Sleep {
+        match self.elapsed {
+            0 => Either::Right(future::pending()),
+            _ => {
+                self.elapsed -= 1;
+                Either::Left(future::ready(()))
+            }
+        }
+    }
+}
+
+pub(crate) fn real_time_data() -> IncomingPackage {
+    IncomingPackage::RealTimeData(RealTimeData {
+        signal_strength: 0,
+        searching_time_too_long: false,
+        low_spo2: false,
+        pulse_beep: false,
+        probe_errors: false,
+        pulse_waveform: 0,
+        searching_pulse: false,
+        bar_graph: 0,
+        pi_invalid: true,
+        pulse_rate: 60,
+        spo2: 98,
+        pi: 0,
+    })
+}
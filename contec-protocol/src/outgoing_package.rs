@@ -1,9 +1,15 @@
 #![allow(clippy::bool_assert_comparison)]
+// The serde derives use `ControlCommand::AskForStorageDataIdentifiers2` outside of its enum
+#![allow(deprecated)]
 
 //! Packages sent to the device
 
+use core::convert::Infallible;
+
 use crate::bit_ops::get_bit;
-use crate::encoding::encode_high_byte;
+use crate::encoding::{decode_high_byte, encode_high_byte};
+use crate::incoming_package::DecodeContext;
+use crate::{Error, Result};
 
 /// A package which can be sent to the device
 pub trait OutgoingPackage {
@@ -30,7 +36,45 @@ where
     buffer
 }
 
+/// Parses the byte representation of a package sent to the device
+pub fn package_from_bytes(bytes: [u8; 9]) -> Result<AnyOutgoingPackage, Infallible> {
+    let [code, high_byte, data @ ..] = bytes;
+    let data =
+        decode_high_byte((high_byte, data)).map_err(|invalid_index| Error::InvalidPackageData {
+            code,
+            invalid_index,
+            context: Some(DecodeContext::new(
+                &bytes,
+                invalid_index + 1,
+                invalid_index as u64 + 1,
+                0,
+            )),
+        })?;
+    match code {
+        ControlCommand::CODE => ControlCommand::from_bytes(data)
+            .map(AnyOutgoingPackage::ControlCommand)
+            .ok_or(Error::UnknownCommandCode { code: data[0] }),
+        SetDeviceId::CODE => Ok(AnyOutgoingPackage::SetDeviceId(SetDeviceId(data))),
+        code => Err(Error::UnknownTypeCode {
+            code,
+            context: Some(DecodeContext::new(&bytes, 0, 0, 0)),
+        }),
+    }
+}
+
+/// Any package which can be sent to the device
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AnyOutgoingPackage {
+    /// Control command
+    ControlCommand(ControlCommand),
+    /// Set new device identifier
+    SetDeviceId(SetDeviceId),
+}
+
 /// Control command
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ControlCommand {
     /// Ask device to start sending real time data
     ContinuousRealTimeData,
@@ -58,13 +102,17 @@ pub enum ControlCommand {
     AskForUserAmount,
     /// Delete storage data
     DeleteStorageData(u8, u8),
-    /// Ask for storage data identifiers
+    /// Ask for the storage data identifiers of all storage segments
     AskForStorageDataIdentifiers,
     /// Synchronize device time
     SynchronizeDeviceTime(u8, u8, u8),
     /// Synchronize device date
     SynchronizeDeviceDate(u8, u8, u8, u8, u8),
     /// Ask for storage data identifiers 2
+    ///
+    /// Encoded with code 0xA3 like [ControlCommand::AskForStorageDataSegmentAmount], so devices
+    /// answer it with the segment amount.
+    #[deprecated(note = "use `ControlCommand::AskForStorageDataIdentifiers` (0xB0) instead")]
     AskForStorageDataIdentifiers2(u8, u8),
 }
 
@@ -110,7 +158,48 @@ impl OutgoingPackage for ControlCommand {
     }
 }
 
+impl ControlCommand {
+    /// Create a control command from the given data bytes
+    ///
+    /// Returns `None` if the command code (first byte) is unknown.
+    pub fn from_bytes(bytes: [u8; 7]) -> Option<Self> {
+        Some(match bytes {
+            [0xA1, ..] => ControlCommand::ContinuousRealTimeData,
+            [0xA2, ..] => ControlCommand::StopRealTimeData,
+            [0xA3, user_index, ..] => ControlCommand::AskForStorageDataSegmentAmount(user_index),
+            [0xA4, user_index, data_segment, ..] => {
+                ControlCommand::AskForStorageDataLength(user_index, data_segment)
+            }
+            [0xA5, user_index, data_segment, ..] => {
+                ControlCommand::AskForStorageStartTime(user_index, data_segment)
+            }
+            [0xA6, user_index, data_segment, ..] => {
+                ControlCommand::AskForStorageData(user_index, data_segment)
+            }
+            [0xA7, ..] => ControlCommand::StopStorageData,
+            [0xAA, ..] => ControlCommand::AskForDeviceIdentifier,
+            [0xAB, user_index, ..] => ControlCommand::AskForUserInformation(user_index),
+            [0xAC, ..] => ControlCommand::AskWhetherSupportPI,
+            [0xAD, ..] => ControlCommand::AskForUserAmount,
+            [0xAE, user_index, data_segment, ..] => {
+                ControlCommand::DeleteStorageData(user_index, data_segment)
+            }
+            [0xAF, ..] => ControlCommand::InformDeviceConnected,
+            [0xB0, ..] => ControlCommand::AskForStorageDataIdentifiers,
+            [0xB1, hour, minute, second, ..] => {
+                ControlCommand::SynchronizeDeviceTime(hour, minute, second)
+            }
+            [0xB2, year_high, year_low, month, day, week, _] => {
+                ControlCommand::SynchronizeDeviceDate(year_high, year_low, month, day, week)
+            }
+            _ => return None,
+        })
+    }
+}
+
 /// Set new device identifier
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SetDeviceId([u8; 7]);
 
 impl SetDeviceId {
@@ -122,6 +211,11 @@ impl SetDeviceId {
         }
         Self(str)
     }
+
+    /// The new device identifier
+    pub fn id(&self) -> &[u8; 7] {
+        &self.0
+    }
 }
 
 impl OutgoingPackage for SetDeviceId {
@@ -131,3 +225,79 @@ impl OutgoingPackage for SetDeviceId {
         self.0
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_control_command_round_trip() {
+        let commands = [
+            ControlCommand::ContinuousRealTimeData,
+            ControlCommand::StopRealTimeData,
+            ControlCommand::InformDeviceConnected,
+            ControlCommand::AskForDeviceIdentifier,
+            ControlCommand::AskForStorageDataSegmentAmount(1),
+            ControlCommand::AskForStorageDataLength(1, 2),
+            ControlCommand::AskForStorageStartTime(1, 2),
+            ControlCommand::AskForStorageData(1, 2),
+            ControlCommand::StopStorageData,
+            ControlCommand::AskForUserInformation(1),
+            ControlCommand::AskWhetherSupportPI,
+            ControlCommand::AskForUserAmount,
+            ControlCommand::DeleteStorageData(1, 2),
+            ControlCommand::AskForStorageDataIdentifiers,
+            ControlCommand::SynchronizeDeviceTime(23, 59, 58),
+            ControlCommand::SynchronizeDeviceDate(20, 22, 12, 31, 6),
+        ];
+        for command in commands {
+            assert_eq!(
+                package_from_bytes(bytes_from_package(command)).unwrap(),
+                AnyOutgoingPackage::ControlCommand(command)
+            );
+        }
+    }
+
+    #[test]
+    fn test_storage_data_identifiers_2() {
+        let command = ControlCommand::AskForStorageDataIdentifiers2(1, 2);
+        assert_eq!(command.bytes(), [0xA3, 1, 2, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_set_device_id_round_trip() {
+        let package = SetDeviceId::new("PO_250a");
+        assert_eq!(
+            package_from_bytes(bytes_from_package(package)).unwrap(),
+            AnyOutgoingPackage::SetDeviceId(package)
+        );
+    }
+
+    #[test]
+    fn test_invalid_packages() {
+        assert!(matches!(
+            package_from_bytes([0x7D, 0x81, 0x99, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80]),
+            Err(Error::UnknownCommandCode { code: 0x99 })
+        ));
+        assert!(matches!(
+            package_from_bytes([0x7E, 0x81, 0xA1, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80]),
+            Err(Error::UnknownTypeCode { code: 0x7E, .. })
+        ));
+        assert!(matches!(
+            package_from_bytes([0x7D, 0x81, 0xA1, 0x80, 0x00, 0x80, 0x80, 0x80, 0x80]),
+            Err(Error::InvalidPackageData {
+                invalid_index: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "serde", feature = "std"))]
+    fn test_serde() {
+        let command = ControlCommand::AskForStorageData(1, 2);
+        let json = serde_json::to_string(&command).unwrap();
+        assert_eq!(json, r#"{"AskForStorageData":[1,2]}"#);
+        assert_eq!(serde_json::from_str::<ControlCommand>(&json).unwrap(), command);
+    }
+}
@@ -1,30 +1,168 @@
 use core::pin::Pin;
-use core::task::Poll;
+use core::task::{Context, Poll};
 
 use futures::{future, ready, Future};
 
-use crate::incoming_package::{IncomingPackage, IncomingStateMachine};
-use crate::outgoing_package::{bytes_from_package, OutgoingPackage};
+use crate::incoming_package::{
+    CustomPackages, FeedbackCode, IncomingPackage, IncomingStateMachine, RealTimeData,
+    StorageDataIdentifiers,
+};
+use crate::outgoing_package::{bytes_from_package, ControlCommand, OutgoingPackage};
+use crate::realtime::RealTimeDevice;
+use crate::storage::StorageDataStream;
 use crate::traits::AsyncReadWrite;
-use crate::{Error, Result};
+use crate::{DateTime, Error, Result};
 
 /// Represents a connection with a pulse oximeter.
 ///
 /// Use the [PulseOximeter::send_package()] and [PulseOximeter::receive_package] methods to
 /// communicate with the foobar device.
+///
+/// Methods like [PulseOximeter::user_amount()] send a single request and wait for the matching
+/// response. Unrelated packages received in the meantime are ignored.
 // pub struct PulseOximeter<T: AsyncRead + AsyncWrite + Unpin> {
 pub struct PulseOximeter<T: AsyncReadWrite + Unpin> {
-    port: T,
-    incoming: IncomingStateMachine,
-    outgoing: OutgoingStatus,
+    pub(crate) port: T,
+    pub(crate) incoming: IncomingStateMachine,
+    pub(crate) outgoing: Outgoing,
+}
+
+/// Maximum number of packages waiting to be sent, see [PulseOximeter::queue_package()]
+pub const OUTGOING_QUEUE_LENGTH: usize = 8;
+
+/// Queue of the outgoing packages
+pub(crate) struct Outgoing {
+    /// Ring buffer of packages waiting to be sent
+    queue: [[u8; 9]; OUTGOING_QUEUE_LENGTH],
+    /// Index of the first queued package
+    start: usize,
+    /// Number of queued packages
+    length: usize,
+    /// Number of bytes of the first queued package which were already written
+    already_sent: usize,
+    /// Command which has to be sent after the queued packages, before any new package
+    pending: Option<ControlCommand>,
+    /// Number of packages which were queued
+    queued: u64,
+    /// Number of packages which were written, but not flushed yet
+    unflushed: u64,
+    /// Number of packages which were written and flushed
+    written: u64,
 }
 
-enum OutgoingStatus {
-    None,
-    Some {
+impl Outgoing {
+    pub(crate) const fn new() -> Self {
+        Self {
+            queue: [[0; 9]; OUTGOING_QUEUE_LENGTH],
+            start: 0,
+            length: 0,
+            already_sent: 0,
+            pending: None,
+            queued: 0,
+            unflushed: 0,
+            written: 0,
+        }
+    }
+
+    /// Append `buffer` to the queue and return its package number, or `None` if the queue is full
+    pub(crate) fn queue(&mut self, buffer: [u8; 9]) -> Option<u64> {
+        // The pending command goes first
+        if let Some(command) = self.pending {
+            self.push(bytes_from_package(command))?;
+            self.pending = None;
+        }
+        self.push(buffer)
+    }
+
+    fn push(&mut self, buffer: [u8; 9]) -> Option<u64> {
+        if self.length == OUTGOING_QUEUE_LENGTH {
+            return None;
+        }
+        self.queue[(self.start + self.length) % OUTGOING_QUEUE_LENGTH] = buffer;
+        self.length += 1;
+        self.queued += 1;
+        Some(self.queued - 1)
+    }
+
+    /// Start sending `buffer`
+    ///
+    /// Must only be called if [Outgoing::poll()] returned `Poll::Ready(Ok(()))`.
+    pub(crate) fn start_send(&mut self, buffer: [u8; 9]) {
+        debug_assert_eq!(self.length, 0);
+        self.queue(buffer);
+    }
+
+    /// Send `command` with the next operation
+    pub(crate) fn send_later(&mut self, command: ControlCommand) {
+        self.pending = Some(command);
+    }
+
+    /// Number of packages which were written and flushed
+    pub(crate) fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Continue sending the queued packages to `port`, until all are written and flushed
+    pub(crate) fn poll<T: AsyncReadWrite + Unpin>(
+        &mut self,
+        cx: &mut Context<'_>,
+        port: &mut T,
+    ) -> Poll<Result<(), T::Error>> {
+        loop {
+            if self.length == 0 {
+                if let Some(command) = self.pending.take() {
+                    self.push(bytes_from_package(command));
+                    continue;
+                }
+                if self.unflushed > 0 {
+                    ready!(Pin::new(&mut *port).poll_flush(cx))?;
+                    self.written += self.unflushed;
+                    self.unflushed = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            let slice = &self.queue[self.start][self.already_sent..];
+            let bytes_written = ready!(Pin::new(&mut *port).poll_write(cx, slice))?;
+            if bytes_written == 0 {
+                return Err(Error::DeviceWriteZero).into();
+            }
+            if bytes_written > slice.len() {
+                return Err(Error::DeviceWriteTooMuch {
+                    requested: slice.len(),
+                    reported: bytes_written,
+                })
+                .into();
+            }
+            self.already_sent += bytes_written;
+            if self.already_sent == 9 {
+                // Current package written completely
+                self.already_sent = 0;
+                self.start = (self.start + 1) % OUTGOING_QUEUE_LENGTH;
+                self.length -= 1;
+                self.unflushed += 1;
+            }
+        }
+    }
+
+    /// Queue `buffer` and wait until it was written and flushed
+    ///
+    /// `number` keeps the package number between polls, it must be `None` for the first poll.
+    pub(crate) fn poll_send<T: AsyncReadWrite + Unpin>(
+        &mut self,
+        cx: &mut Context<'_>,
+        port: &mut T,
         buffer: [u8; 9],
-        already_sent: usize,
-    },
+        number: &mut Option<u64>,
+    ) -> Poll<Result<(), T::Error>> {
+        while number.is_none() {
+            // Make room for the package by sending the queued packages
+            *number = self.queue(buffer);
+            if number.is_none() {
+                ready!(self.poll(cx, port))?;
+            }
+        }
+        self.poll(cx, port)
+    }
 }
 
 impl<T: AsyncReadWrite + Unpin> PulseOximeter<T> {
@@ -32,73 +170,496 @@ impl<T: AsyncReadWrite + Unpin> PulseOximeter<T> {
     pub fn new(port: T) -> Self {
         Self {
             port,
-            incoming: IncomingStateMachine::None,
-            outgoing: OutgoingStatus::None,
+            incoming: IncomingStateMachine::new(),
+            outgoing: Outgoing::new(),
         }
     }
 
+    /// Enable or disable resynchronization of the incoming byte stream.
+    ///
+    /// See [IncomingStateMachine::set_resynchronize()] for details.
+    pub fn set_resynchronize(&mut self, resynchronize: bool) {
+        self.incoming.set_resynchronize(resynchronize);
+    }
+
+    /// Decode vendor-specific packages as [IncomingPackage::Custom].
+    ///
+    /// See [IncomingStateMachine::set_custom_packages()] for details.
+    pub fn set_custom_packages(&mut self, custom: CustomPackages) {
+        self.incoming.set_custom_packages(custom);
+    }
+
+    /// Total number of incoming bytes which were dropped during resynchronization.
+    pub fn skipped_bytes(&self) -> usize {
+        self.incoming.skipped_bytes()
+    }
+
     /// Send a package to the device.
     ///
-    /// Note that if a future returned by a previous call to this function was not polled until
-    /// completion, the rest of the package of the previous call will be sent before the new
-    /// package will be sent.
-    #[allow(clippy::bool_assert_comparison)]
+    /// The package is appended to the outgoing queue, so queued packages are sent first. This
+    /// includes the rest of a package of a previous call, whose future was not polled until
+    /// completion. The returned future completes once the package was written and the port was
+    /// flushed.
     pub fn send_package<P>(&mut self, package: P) -> impl Future<Output = Result<(), T::Error>> + '_
     where
         P: OutgoingPackage,
     {
         let buffer = bytes_from_package(package);
+        let mut number = None;
+        future::poll_fn(move |cx| self.outgoing.poll_send(cx, &mut self.port, buffer, &mut number))
+    }
 
-        // Determine whether the last send operation was completed
-        let mut unfinished_send = matches!(self.outgoing, OutgoingStatus::Some { .. });
-
-        future::poll_fn(move |cx| loop {
-            match self.outgoing {
-                // No ongoing send operation, so start next one
-                OutgoingStatus::None => {
-                    self.outgoing = OutgoingStatus::Some {
-                        buffer,
-                        already_sent: 0,
-                    }
-                }
-                // Ongoing send operation
-                OutgoingStatus::Some {
-                    buffer,
-                    ref mut already_sent,
-                } => {
-                    let slice = &buffer[*already_sent..9];
-                    let bytes_written = ready!(Pin::new(&mut self.port).poll_write(cx, slice))?;
-                    if bytes_written > slice.len() {
-                        return Err(Error::DeviceWriteTooMuch {
-                            requested: slice.len(),
-                            reported: bytes_written,
-                        })
-                        .into();
-                    }
-                    *already_sent += bytes_written;
-                    if *already_sent == 9 {
-                        // Current send operation finished
-                        self.outgoing = OutgoingStatus::None;
-                        if unfinished_send {
-                            // Old send operation is now finished, start sending actual data of
-                            // this function call
-                            unfinished_send = false;
-                        } else {
-                            // Send operation completed, return
-                            return Poll::Ready(Ok(()));
-                        }
-                    }
-                }
-            }
-        })
+    /// Append a package to the outgoing queue, without waiting until it is sent.
+    ///
+    /// Queued packages are sent with the next operation on the device, or by
+    /// [PulseOximeter::flush()]. Returns the number of the package, see
+    /// [PulseOximeter::written_packages()]. Fails with [Error::QueueFull] if
+    /// [OUTGOING_QUEUE_LENGTH] packages are already waiting.
+    pub fn queue_package<P>(&mut self, package: P) -> Result<u64, T::Error>
+    where
+        P: OutgoingPackage,
+    {
+        self.outgoing.queue(bytes_from_package(package)).ok_or(Error::QueueFull)
+    }
+
+    /// Send all queued packages and flush the port.
+    pub fn flush(&mut self) -> impl Future<Output = Result<(), T::Error>> + '_ {
+        future::poll_fn(move |cx| self.poll_outgoing(cx))
+    }
+
+    /// Number of packages which were written to the port and flushed.
+    ///
+    /// Packages are numbered in the order in which they were queued, starting at 0. This includes
+    /// packages sent by [PulseOximeter::send_package()] and the request methods. The package with
+    /// number `n` was written if `n < written_packages()`.
+    pub fn written_packages(&self) -> u64 {
+        self.outgoing.written()
     }
 
     /// Receive the next package from the device.
+    ///
+    /// Unfinished send operations are completed first.
     pub fn receive_package(
         &mut self,
     ) -> impl Future<Output = Result<IncomingPackage, T::Error>> + '_ {
         future::poll_fn(move |cx| {
-            self.incoming.resume(|buf| Pin::new(&mut self.port).poll_read(cx, buf))
+            ready!(self.poll_outgoing(cx))?;
+            self.poll_receive(cx)
+        })
+    }
+
+    /// Start sending `buffer`
+    ///
+    /// Must only be called if [PulseOximeter::poll_outgoing()] returned `Poll::Ready(Ok(()))`.
+    pub(crate) fn start_send(&mut self, buffer: [u8; 9]) {
+        self.outgoing.start_send(buffer);
+    }
+
+    /// Start sending `command` as soon as previous send operations are finished
+    fn poll_start_send(
+        &mut self,
+        cx: &mut Context<'_>,
+        command: ControlCommand,
+    ) -> Poll<Result<(), T::Error>> {
+        ready!(self.poll_outgoing(cx))?;
+        self.start_send(bytes_from_package(command));
+        Poll::Ready(Ok(()))
+    }
+
+    /// Send `command` with the next operation
+    pub(crate) fn send_later(&mut self, command: ControlCommand) {
+        self.outgoing.send_later(command);
+    }
+
+    /// Continue sending the queued packages, until all are written and flushed
+    pub(crate) fn poll_outgoing(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), T::Error>> {
+        self.outgoing.poll(cx, &mut self.port)
+    }
+
+    /// Continue receiving the next package
+    pub(crate) fn poll_receive(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<IncomingPackage, T::Error>> {
+        self.incoming.resume(|buf| Pin::new(&mut self.port).poll_read(cx, buf))
+    }
+
+    /// Download the samples of a storage segment.
+    ///
+    /// Asks for the data length of the segment first, see [StorageDataStream] for details. The
+    /// format of the samples is detected from the first storage data package, use
+    /// [PulseOximeter::storage_data_with()] if the storage data identifiers are known.
+    pub async fn storage_data(
+        &mut self,
+        user_index: u8,
+        segment: u8,
+    ) -> Result<StorageDataStream<'_, T>, T::Error> {
+        let length = self.storage_length(user_index, segment).await?;
+        Ok(StorageDataStream::new(self, user_index, segment, length, None))
+    }
+
+    /// Download the samples of the storage segment described by `identifiers`.
+    ///
+    /// Same as [PulseOximeter::storage_data()], but the format of the samples is taken from the
+    /// [StorageDataIdentifiers] (see [PulseOximeter::storage_identifiers()]).
+    pub async fn storage_data_with(
+        &mut self,
+        identifiers: StorageDataIdentifiers,
+    ) -> Result<StorageDataStream<'_, T>, T::Error> {
+        let (user_index, segment) = (identifiers.user_index, identifiers.data_segment);
+        let length = self.storage_length(user_index, segment).await?;
+        Ok(StorageDataStream::new(self, user_index, segment, length, Some(identifiers)))
+    }
+
+    /// Send `command` and wait until `response` returns `Some` for a received package.
+    ///
+    /// Fails if the device answers `command` with an unsuccessful command feedback.
+    async fn request<R>(
+        &mut self,
+        command: ControlCommand,
+        mut response: impl FnMut(IncomingPackage) -> Option<R>,
+    ) -> Result<R, T::Error> {
+        let code = command.bytes()[0];
+        self.send_package(command).await?;
+        loop {
+            let package = self.receive_package().await?;
+            if let IncomingPackage::CommandFeedback(feedback) = package {
+                if feedback.command == code && feedback.code != FeedbackCode::Completed {
+                    return Err(Error::from_feedback(feedback));
+                }
+            }
+            if let Some(result) = response(package) {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Send `marker` and drop all packages until the device answers it.
+    ///
+    /// The device answers commands in order, so all outstanding answers to previous commands are
+    /// dropped. `marker` must be `AskForDeviceIdentifier` or `AskForUserAmount`, a device which does
+    /// not support it answers with a command feedback instead.
+    pub(crate) async fn sync(&mut self, marker: ControlCommand) -> Result<(), T::Error> {
+        let code = marker.bytes()[0];
+        let result = self
+            .request(marker, |package| match (marker, package) {
+                (ControlCommand::AskForDeviceIdentifier, IncomingPackage::DeviceIdentifier(_))
+                | (ControlCommand::AskForUserAmount, IncomingPackage::UserAmount(_)) => Some(()),
+                _ => None,
+            })
+            .await;
+        match result {
+            Err(Error::NotSupported { command }) if command == code => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Send `command` and wait for a successful command feedback.
+    async fn command(&mut self, command: ControlCommand) -> Result<(), T::Error> {
+        let code = command.bytes()[0];
+        self.request(command, |package| match package {
+            IncomingPackage::CommandFeedback(feedback) if feedback.command == code => Some(()),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Stop sending real time data and wait until the device is free.
+    pub async fn stop_real_time_data(&mut self) -> Result<(), T::Error> {
+        self.request(ControlCommand::StopRealTimeData, |package| match package {
+            IncomingPackage::FreeFeedback(_) => Some(()),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Ask for the device identifier.
+    pub async fn device_identifier(&mut self) -> Result<[u8; 7], T::Error> {
+        self.request(ControlCommand::AskForDeviceIdentifier, |package| match package {
+            IncomingPackage::DeviceIdentifier(p) => Some(p.identifier),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Ask for the number of users.
+    pub async fn user_amount(&mut self) -> Result<u8, T::Error> {
+        self.request(ControlCommand::AskForUserAmount, |package| match package {
+            IncomingPackage::UserAmount(p) => Some(p.total_user),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Ask for the number of storage segments of user `user_index`.
+    pub async fn segment_amount(&mut self, user_index: u8) -> Result<u8, T::Error> {
+        let command = ControlCommand::AskForStorageDataSegmentAmount(user_index);
+        self.request(command, |package| match package {
+            IncomingPackage::StorageDataSegmentAmount(p) if p.user_index == user_index => {
+                Some(p.segment_amount)
+            }
+            _ => None,
+        })
+        .await
+    }
+
+    /// Ask for the start time of a storage segment.
+    pub async fn storage_start_time(
+        &mut self,
+        user_index: u8,
+        segment: u8,
+    ) -> Result<DateTime, T::Error> {
+        let command = ControlCommand::AskForStorageStartTime(user_index, segment);
+        let (mut date, mut time) = (None, None);
+        self.request(command, |package| {
+            match package {
+                IncomingPackage::StorageStartTimeDate(p)
+                    if (p.user_index, p.storage_segment) == (user_index, segment) =>
+                {
+                    date = Some(p)
+                }
+                IncomingPackage::StorageStartTimeTime(p)
+                    if (p.user_index, p.storage_segment) == (user_index, segment) =>
+                {
+                    time = Some(p)
+                }
+                _ => {}
+            }
+            Some(DateTime {
+                year: date?.year,
+                month: date?.month,
+                day: date?.day,
+                hour: time?.hour,
+                minute: time?.minute,
+                second: time?.second,
+            })
+        })
+        .await
+    }
+
+    /// Ask for the data length (in bytes) of a storage segment.
+    pub async fn storage_length(&mut self, user_index: u8, segment: u8) -> Result<u32, T::Error> {
+        let command = ControlCommand::AskForStorageDataLength(user_index, segment);
+        self.request(command, |package| match package {
+            IncomingPackage::StorageDataLength(p)
+                if (p.user_index, p.data_segment) == (user_index, segment) =>
+            {
+                Some(p.length)
+            }
+            _ => None,
+        })
+        .await
+    }
+
+    /// Ask for the storage data identifiers of a storage segment.
+    ///
+    /// The identifiers tell whether the segment contains perfusion index. The device sends the
+    /// identifiers of all segments, the ones of other segments are ignored.
+    pub async fn storage_identifiers(
+        &mut self,
+        user_index: u8,
+        segment: u8,
+    ) -> Result<StorageDataIdentifiers, T::Error> {
+        self.request(ControlCommand::AskForStorageDataIdentifiers, |package| match package {
+            IncomingPackage::StorageDataIdentifiers(p)
+                if (p.user_index, p.data_segment) == (user_index, segment) =>
+            {
+                Some(p)
+            }
+            _ => None,
         })
+        .await
+    }
+
+    /// Delete a storage segment.
+    pub async fn delete_segment(&mut self, user_index: u8, segment: u8) -> Result<(), T::Error> {
+        self.command(ControlCommand::DeleteStorageData(user_index, segment)).await
+    }
+
+    /// Set the date and time of the device clock.
+    ///
+    /// Fails with [Error::InvalidMonth] without sending anything if the month is not in `1..=12`.
+    pub async fn set_date_time(&mut self, date_time: DateTime) -> Result<(), T::Error> {
+        let weekday = date_time.weekday().ok_or(Error::InvalidMonth {
+            month: date_time.month,
+        })?;
+        self.command(ControlCommand::SynchronizeDeviceDate(
+            (date_time.year / 100) as u8,
+            (date_time.year % 100) as u8,
+            date_time.month,
+            date_time.day,
+            weekday,
+        ))
+        .await?;
+        self.command(ControlCommand::SynchronizeDeviceTime(
+            date_time.hour,
+            date_time.minute,
+            date_time.second,
+        ))
+        .await
+    }
+}
+
+impl<T: AsyncReadWrite + Unpin> RealTimeDevice for PulseOximeter<T> {
+    type Error = T::Error;
+
+    fn poll_start_real_time_data(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), T::Error>> {
+        self.poll_start_send(cx, ControlCommand::ContinuousRealTimeData)
+    }
+
+    fn poll_keep_alive(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), T::Error>> {
+        self.poll_start_send(cx, ControlCommand::InformDeviceConnected)
+    }
+
+    fn poll_real_time_data(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<RealTimeData, T::Error>> {
+        loop {
+            ready!(self.poll_outgoing(cx))?;
+            if let IncomingPackage::RealTimeData(data) = ready!(self.poll_receive(cx))? {
+                return Poll::Ready(Ok(data));
+            }
+        }
+    }
+
+    fn stop_real_time_data_later(&mut self) {
+        self.send_later(ControlCommand::StopRealTimeData);
+    }
+
+    fn poll_real_time_data_stopped(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), T::Error>> {
+        let code = ControlCommand::StopRealTimeData.bytes()[0];
+        loop {
+            ready!(self.poll_outgoing(cx))?;
+            match ready!(self.poll_receive(cx))? {
+                IncomingPackage::FreeFeedback(_) => return Poll::Ready(Ok(())),
+                IncomingPackage::CommandFeedback(feedback)
+                    if feedback.command == code && feedback.code != FeedbackCode::Completed =>
+                {
+                    return Err(Error::from_feedback(feedback)).into();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn skipped_bytes(&self) -> usize {
+        PulseOximeter::skipped_bytes(self)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::incoming_package::{CommandFeedback, PISupport, UserAmount};
+    use crate::mock::{real_time_data, MockPort};
+    use crate::outgoing_package::AnyOutgoingPackage;
+
+    #[test]
+    fn test_request_ignores_unrelated_packages() {
+        let mut device = PulseOximeter::new(MockPort::new(&[
+            real_time_data(),
+            IncomingPackage::UserAmount(UserAmount { total_user: 2 }),
+        ]));
+        assert_eq!(block_on(device.user_amount()).unwrap(), 2);
+        assert_eq!(device.port.sent_commands(), [AnyOutgoingPackage::ControlCommand(
+            ControlCommand::AskForUserAmount
+        )]);
+    }
+
+    #[test]
+    fn test_storage_identifiers() {
+        let identifiers = |data_segment| {
+            IncomingPackage::StorageDataIdentifiers(StorageDataIdentifiers {
+                user_index: 0,
+                data_segment,
+                pi_identifiers: 0x01,
+                retention: [0; 4],
+            })
+        };
+        let mut device = PulseOximeter::new(MockPort::new(&[identifiers(0), identifiers(1)]));
+        let identifiers = block_on(device.storage_identifiers(0, 1)).unwrap();
+        assert_eq!(identifiers.data_segment, 1);
+        assert_eq!(identifiers.pi_support(), PISupport::Supported);
+        assert!(identifiers.with_pi());
+        assert_eq!(device.port.sent_commands(), [AnyOutgoingPackage::ControlCommand(
+            ControlCommand::AskForStorageDataIdentifiers
+        )]);
+    }
+
+    #[test]
+    fn test_set_date_time_invalid_month() {
+        let mut device = PulseOximeter::new(MockPort::new(&[]));
+        let date_time = DateTime {
+            year: 2022,
+            month: 0,
+            day: 1,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        };
+        let result = block_on(device.set_date_time(date_time));
+        assert!(matches!(result, Err(Error::InvalidMonth { month: 0 })));
+        assert!(device.port.sent_commands().is_empty());
+    }
+
+    #[test]
+    fn test_outgoing_queue() {
+        let mut device = PulseOximeter::new(MockPort::new(&[]));
+        for number in 0..OUTGOING_QUEUE_LENGTH as u64 {
+            assert_eq!(
+                device.queue_package(ControlCommand::InformDeviceConnected).unwrap(),
+                number
+            );
+        }
+        assert!(matches!(
+            device.queue_package(ControlCommand::StopRealTimeData),
+            Err(Error::QueueFull)
+        ));
+        assert_eq!(device.written_packages(), 0);
+
+        block_on(device.flush()).unwrap();
+        assert_eq!(device.written_packages(), OUTGOING_QUEUE_LENGTH as u64);
+        assert_eq!(device.port.flushed, 9 * OUTGOING_QUEUE_LENGTH);
+
+        block_on(device.send_package(ControlCommand::StopRealTimeData)).unwrap();
+        assert_eq!(device.written_packages(), OUTGOING_QUEUE_LENGTH as u64 + 1);
+        assert_eq!(device.port.flushed, 9 * (OUTGOING_QUEUE_LENGTH + 1));
+        assert_eq!(
+            device.port.sent_commands().last(),
+            Some(&AnyOutgoingPackage::ControlCommand(ControlCommand::StopRealTimeData))
+        );
+    }
+
+    #[test]
+    fn test_request_command_feedback() {
+        let mut device = PulseOximeter::new(MockPort::new(&[
+            IncomingPackage::CommandFeedback(CommandFeedback {
+                command: 0xB2,
+                code: FeedbackCode::Completed,
+            }),
+            IncomingPackage::CommandFeedback(CommandFeedback {
+                command: 0xB1,
+                code: FeedbackCode::NotSupported,
+            }),
+        ]));
+        let date_time = DateTime {
+            year: 2022,
+            month: 6,
+            day: 20,
+            hour: 22,
+            minute: 15,
+            second: 0,
+        };
+        let result = block_on(device.set_date_time(date_time));
+        assert!(matches!(result, Err(Error::NotSupported { command: 0xB1 })));
+        assert_eq!(device.port.sent_commands(), [
+            AnyOutgoingPackage::ControlCommand(ControlCommand::SynchronizeDeviceDate(
+                20, 22, 6, 20, 1
+            )),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::SynchronizeDeviceTime(22, 15, 0)),
+        ]);
     }
 }
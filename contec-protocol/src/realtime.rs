@@ -0,0 +1,205 @@
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use futures::{future, ready, Future, Stream};
+
+use crate::incoming_package::RealTimeData;
+use crate::{Result, Timer};
+
+/// Real time data interface, which is shared by all supported protocol generations.
+///
+/// Implemented by [PulseOximeter](crate::PulseOximeter) (protocol V7.0) and
+/// [LegacyPulseOximeter](crate::LegacyPulseOximeter) (5 byte real time data of older devices).
+/// The methods are driven by [RealTimeDataStream], use [RealTimeDevice::realtime()] to receive
+/// real time data.
+pub trait RealTimeDevice {
+    /// Device error
+    #[cfg(feature = "std")]
+    type Error: snafu::AsErrorSource;
+    /// Device error
+    #[cfg(not(feature = "std"))]
+    type Error;
+
+    /// Ask the device to start sending real time data.
+    fn poll_start_real_time_data(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>>;
+
+    /// Inform the device that it is still connected, so that it keeps sending real time data.
+    fn poll_keep_alive(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>>;
+
+    /// Receive the next real time data sample, other data sent by the device is ignored.
+    fn poll_real_time_data(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<RealTimeData, Self::Error>>;
+
+    /// Ask the device to stop sending real time data with the next operation.
+    fn stop_real_time_data_later(&mut self);
+
+    /// Wait until the device stopped sending real time data.
+    ///
+    /// Must be called after [RealTimeDevice::stop_real_time_data_later()].
+    fn poll_real_time_data_stopped(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>>;
+
+    /// Total number of incoming bytes which were dropped during resynchronization.
+    fn skipped_bytes(&self) -> usize;
+
+    /// Start receiving real time data, using `timer` to keep the device alive.
+    ///
+    /// See [RealTimeDataStream] for details.
+    fn realtime<M: Timer>(&mut self, timer: M) -> RealTimeDataStream<'_, Self, M>
+    where
+        Self: Sized,
+    {
+        RealTimeDataStream::new(self, timer)
+    }
+}
+
+/// Time after which the device is informed that it is still connected
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(4);
+
+/// Stream of real time data, created by [RealTimeDevice::realtime()].
+///
+/// The device is asked to send real time data when the stream is polled for the first time. While
+/// the stream is polled, the device is informed every 4 seconds that it is still connected, to
+/// keep it sending, also if it does not send any samples in the meantime. For protocol V7.0 this
+/// means that `ContinuousRealTimeData` and `InformDeviceConnected` are sent. Packages other than
+/// [RealTimeData] are ignored.
+///
+/// Use [RealTimeDataStream::stop()] to stop the transmission. If the stream is dropped instead,
+/// the device is stopped with the next operation on the device.
+pub struct RealTimeDataStream<'a, D: RealTimeDevice, M: Timer> {
+    device: Option<&'a mut D>,
+    state: State,
+    timer: M,
+    /// Delay until the next keep-alive, `None` if it is due
+    keep_alive: Option<M::Sleep>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum State {
+    /// Real time data was not requested yet
+    Idle,
+    /// Receiving real time data
+    Running,
+}
+
+impl<'a, D: RealTimeDevice, M: Timer> RealTimeDataStream<'a, D, M> {
+    pub(crate) fn new(device: &'a mut D, timer: M) -> Self {
+        Self {
+            device: Some(device),
+            state: State::Idle,
+            timer,
+            keep_alive: None,
+        }
+    }
+
+    /// The underlying device.
+    pub fn device(&self) -> &D {
+        self.device.as_ref().unwrap()
+    }
+
+    /// Stop real time data and wait until the device is free.
+    pub fn stop(mut self) -> impl Future<Output = Result<(), D::Error>> + 'a {
+        let device = self.device.take().unwrap();
+        device.stop_real_time_data_later();
+        future::poll_fn(move |cx| device.poll_real_time_data_stopped(cx))
+    }
+}
+
+impl<D: RealTimeDevice, M: Timer + Unpin> Stream for RealTimeDataStream<'_, D, M> {
+    type Item = Result<RealTimeData, D::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let device = this.device.as_mut().unwrap();
+        if this.state == State::Idle {
+            ready!(device.poll_start_real_time_data(cx))?;
+            this.state = State::Running;
+            this.keep_alive = Some(this.timer.sleep(KEEP_ALIVE_INTERVAL));
+        }
+        // Polling the delay also wakes the task if the device does not send anything
+        loop {
+            match &mut this.keep_alive {
+                Some(sleep) => match Pin::new(sleep).poll(cx) {
+                    Poll::Ready(()) => this.keep_alive = None,
+                    Poll::Pending => break,
+                },
+                None => {
+                    ready!(device.poll_keep_alive(cx))?;
+                    this.keep_alive = Some(this.timer.sleep(KEEP_ALIVE_INTERVAL));
+                }
+            }
+        }
+        let data = ready!(device.poll_real_time_data(cx))?;
+        Poll::Ready(Some(Ok(data)))
+    }
+}
+
+impl<D: RealTimeDevice, M: Timer> Drop for RealTimeDataStream<'_, D, M> {
+    fn drop(&mut self) {
+        if let Some(device) = self.device.take() {
+            if self.state == State::Running {
+                device.stop_real_time_data_later();
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::incoming_package::{FreeFeedback, IncomingPackage};
+    use crate::mock::{real_time_data, MockPort, MockTimer};
+    use crate::outgoing_package::{AnyOutgoingPackage, ControlCommand};
+    use crate::PulseOximeter;
+
+    #[test]
+    fn test_keep_alive_and_stop() {
+        let mut device = PulseOximeter::new(MockPort::new(&[
+            real_time_data(),
+            real_time_data(),
+            IncomingPackage::FreeFeedback(FreeFeedback {}),
+        ]));
+
+        block_on(async {
+            // The first delay elapses before the first sample
+            let mut stream = device.realtime(MockTimer { elapsed: 1 });
+            for _ in 0..2 {
+                assert_eq!(stream.next().await.unwrap().unwrap().spo2, 98);
+            }
+            stream.stop().await.unwrap();
+        });
+        assert_eq!(device.port.sent_commands(), [
+            AnyOutgoingPackage::ControlCommand(ControlCommand::ContinuousRealTimeData),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::InformDeviceConnected),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::StopRealTimeData),
+        ]);
+    }
+
+    #[test]
+    fn test_stop_on_drop() {
+        let mut device = PulseOximeter::new(MockPort::new(&[
+            real_time_data(),
+            IncomingPackage::FreeFeedback(FreeFeedback {}),
+        ]));
+
+        block_on(async {
+            let mut stream = device.realtime(MockTimer { elapsed: 0 });
+            stream.next().await.unwrap().unwrap();
+            drop(stream);
+            let package = device.receive_package().await.unwrap();
+            assert!(matches!(package, IncomingPackage::FreeFeedback(_)));
+        });
+        assert_eq!(device.port.sent_commands(), [
+            AnyOutgoingPackage::ControlCommand(ControlCommand::ContinuousRealTimeData),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::StopRealTimeData),
+        ]);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::outgoing_package::ControlCommand;
+use crate::realtime::RealTimeDevice;
+use crate::storage::StorageDataStream;
+use crate::timer::{self, RetryPolicy, Timed, Timer};
+use crate::traits::AsyncReadWrite;
+use crate::{PulseOximeter, RealTimeDataStream, Result};
+
+/// What the device is doing, see [Session]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Device answers requests
+    Idle,
+    /// Device sends real time data
+    Streaming,
+    /// Device sends storage data
+    Downloading,
+}
+
+/// Session with a pulse oximeter, which keeps track of the mode of the device.
+///
+/// Real time data and storage data can only be received in the matching mode, and requests only
+/// be sent in [Mode::Idle], because the device interleaves the packages otherwise. The session
+/// switches modes automatically: it sends the `Stop*` command of the current mode and drops all
+/// packages which were still sent before handing the connection back. To find the end of these
+/// leftovers, the session asks for the device identifier and waits for the answer, which the
+/// device sends after all leftovers. If the answer does not arrive within the timeout of the
+/// [RetryPolicy], switching modes fails with [Error::Timeout](crate::Error::Timeout).
+///
+/// In [Mode::Idle], only requests with a single response can be sent, see [Session::idle()]. The
+/// streams returned by [Session::realtime()] and [Session::storage_data()] borrow the session, so
+/// no other command can be sent while they are used.
+pub struct Session<T: AsyncReadWrite + Unpin, M: Timer> {
+    device: PulseOximeter<T>,
+    timer: M,
+    policy: RetryPolicy,
+    /// Current mode, `None` if unknown
+    mode: Option<Mode>,
+}
+
+impl<T: AsyncReadWrite + Unpin, M: Timer> Session<T, M> {
+    /// Start a session with `device`, using `timer` and `policy` for all requests.
+    ///
+    /// The mode of the device is unknown, e.g. it may still send real time data requested by
+    /// another program. Both real time data and storage data are stopped when the session
+    /// switches modes for the first time.
+    pub fn new(device: PulseOximeter<T>, timer: M, policy: RetryPolicy) -> Self {
+        Self {
+            device,
+            timer,
+            policy,
+            mode: None,
+        }
+    }
+
+    /// Current mode of the device, `None` if unknown.
+    pub fn mode(&self) -> Option<Mode> {
+        self.mode
+    }
+
+    /// Switch to [Mode::Idle] to send requests.
+    ///
+    /// The requests are sent with the timeout and retries of the session, see [Timed].
+    pub async fn idle(&mut self) -> Result<Timed<'_, T, &mut M>, T::Error> {
+        if self.mode != Some(Mode::Idle) {
+            self.stop().await?;
+            self.mode = Some(Mode::Idle);
+        }
+        Ok(self.device.timed(&mut self.timer, self.policy))
+    }
+
+    /// Switch to [Mode::Streaming] and start receiving real time data.
+    ///
+    /// See [RealTimeDataStream] for details.
+    pub async fn realtime(
+        &mut self,
+    ) -> Result<RealTimeDataStream<'_, PulseOximeter<T>, &mut M>, T::Error> {
+        self.idle().await?;
+        self.mode = Some(Mode::Streaming);
+        Ok(self.device.realtime(&mut self.timer))
+    }
+
+    /// Switch to [Mode::Downloading] and start downloading the samples of a storage segment.
+    ///
+    /// See [PulseOximeter::storage_data()] for details.
+    pub async fn storage_data(
+        &mut self,
+        user_index: u8,
+        segment: u8,
+    ) -> Result<StorageDataStream<'_, T>, T::Error> {
+        self.idle().await?;
+        self.mode = Some(Mode::Downloading);
+        self.device.storage_data(user_index, segment).await
+    }
+
+    /// Stop the current mode and drop the packages which were sent before
+    async fn stop(&mut self) -> Result<(), T::Error> {
+        if self.mode != Some(Mode::Downloading) {
+            self.device.send_package(ControlCommand::StopRealTimeData).await?;
+        }
+        if self.mode != Some(Mode::Streaming) {
+            self.device.send_package(ControlCommand::StopStorageData).await?;
+        }
+        let drain = self.device.sync(ControlCommand::AskForDeviceIdentifier);
+        timer::timeout(&mut self.timer, self.policy.timeout, drain).await
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use futures::executor::block_on;
+    use futures::{StreamExt, TryStreamExt};
+
+    use super::*;
+    use crate::incoming_package::{
+        CommandFeedback, DeviceIdentifier, FeedbackCode, FreeFeedback, IncomingPackage,
+        StorageData, StorageDataLength, UserAmount,
+    };
+    use crate::mock::{real_time_data, MockPort, MockTimer, SilentPort};
+    use crate::outgoing_package::AnyOutgoingPackage;
+    use crate::Error;
+
+    fn session<T: AsyncReadWrite + Unpin>(port: T) -> Session<T, MockTimer> {
+        Session::new(PulseOximeter::new(port), MockTimer { elapsed: 0 }, RetryPolicy::default())
+    }
+
+    fn identifier() -> IncomingPackage {
+        IncomingPackage::DeviceIdentifier(DeviceIdentifier {
+            identifier: *b"PO_250a",
+        })
+    }
+
+    fn storage_data() -> IncomingPackage {
+        IncomingPackage::StorageData(StorageData {
+            spo2_1: 97,
+            pulse_rate_1: 60,
+            spo2_2: 97,
+            pulse_rate_2: 60,
+            spo2_3: 97,
+            pulse_rate_3: 60,
+        })
+    }
+
+    #[test]
+    fn test_drain_storage_data() {
+        let mut session = session(MockPort::new(&[
+            real_time_data(),
+            identifier(),
+            IncomingPackage::StorageDataLength(StorageDataLength {
+                user_index: 0,
+                data_segment: 1,
+                length: 60,
+            }),
+            storage_data(),
+            storage_data(),
+            storage_data(),
+            identifier(),
+            IncomingPackage::UserAmount(UserAmount { total_user: 1 }),
+        ]));
+        assert_eq!(session.mode(), None);
+
+        block_on(async {
+            let mut samples = session.storage_data(0, 1).await.unwrap();
+            assert_eq!(samples.next().await.unwrap().unwrap().spo2, 97);
+            drop(samples);
+            assert_eq!(session.mode(), Some(Mode::Downloading));
+
+            let mut device = session.idle().await.unwrap();
+            assert_eq!(device.user_amount().await.unwrap(), 1);
+        });
+        assert_eq!(session.mode(), Some(Mode::Idle));
+        assert_eq!(
+            session.device.port.sent_commands(),
+            [
+                ControlCommand::StopRealTimeData,
+                ControlCommand::StopStorageData,
+                ControlCommand::AskForDeviceIdentifier,
+                ControlCommand::AskForStorageDataLength(0, 1),
+                ControlCommand::AskForStorageData(0, 1),
+                ControlCommand::StopStorageData,
+                ControlCommand::StopStorageData,
+                ControlCommand::AskForDeviceIdentifier,
+                ControlCommand::AskForUserAmount,
+            ]
+            .map(AnyOutgoingPackage::ControlCommand)
+        );
+    }
+
+    #[test]
+    fn test_switch_from_streaming() {
+        let not_supported = || {
+            IncomingPackage::CommandFeedback(CommandFeedback {
+                command: 0xAA,
+                code: FeedbackCode::NotSupported,
+            })
+        };
+        let mut session = session(MockPort::new(&[
+            IncomingPackage::FreeFeedback(FreeFeedback {}),
+            not_supported(),
+            real_time_data(),
+            real_time_data(),
+            IncomingPackage::FreeFeedback(FreeFeedback {}),
+            real_time_data(),
+            not_supported(),
+            IncomingPackage::StorageDataLength(StorageDataLength {
+                user_index: 0,
+                data_segment: 0,
+                length: 0,
+            }),
+        ]));
+
+        block_on(async {
+            let mut samples = session.realtime().await.unwrap();
+            assert_eq!(samples.next().await.unwrap().unwrap().spo2, 98);
+            drop(samples);
+            assert_eq!(session.mode(), Some(Mode::Streaming));
+
+            let samples = session.storage_data(0, 0).await.unwrap();
+            assert!(samples.try_collect::<Vec<_>>().await.unwrap().is_empty());
+        });
+        assert_eq!(session.mode(), Some(Mode::Downloading));
+        assert_eq!(
+            session.device.port.sent_commands(),
+            [
+                ControlCommand::StopRealTimeData,
+                ControlCommand::StopStorageData,
+                ControlCommand::AskForDeviceIdentifier,
+                ControlCommand::ContinuousRealTimeData,
+                ControlCommand::StopRealTimeData,
+                ControlCommand::StopRealTimeData,
+                ControlCommand::AskForDeviceIdentifier,
+                ControlCommand::AskForStorageDataLength(0, 0),
+            ]
+            .map(AnyOutgoingPackage::ControlCommand)
+        );
+    }
+
+    #[test]
+    fn test_drain_timeout() {
+        let mut session = Session::new(
+            PulseOximeter::new(SilentPort {
+                mock: MockPort::new(&[]),
+                ignored: 0,
+            }),
+            MockTimer { elapsed: 1 },
+            RetryPolicy::default(),
+        );
+        assert!(matches!(block_on(session.idle()), Err(Error::Timeout)));
+        assert_eq!(session.mode(), None);
+    }
+}
@@ -0,0 +1,199 @@
+//! Independent receive and send halves of a [PulseOximeter]
+extern crate std;
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use futures::{future, Future};
+
+use crate::incoming_package::{IncomingPackage, IncomingStateMachine};
+use crate::outgoing_package::{bytes_from_package, OutgoingPackage};
+use crate::pulse_oximeter::Outgoing;
+use crate::traits::AsyncReadWrite;
+use crate::{Error, PulseOximeter, Result};
+
+/// Port shared by both halves.
+///
+/// The lock is only held while the port is polled, so a pending receive never blocks sending.
+struct SharedPort<T>(Mutex<T>);
+
+impl<T> SharedPort<T> {
+    fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Receiving half of a [PulseOximeter], created by [PulseOximeter::split()].
+pub struct ReceiveHalf<T: AsyncReadWrite + Unpin> {
+    port: Arc<SharedPort<T>>,
+    incoming: IncomingStateMachine,
+}
+
+/// Sending half of a [PulseOximeter], created by [PulseOximeter::split()].
+pub struct SendHalf<T: AsyncReadWrite + Unpin> {
+    port: Arc<SharedPort<T>>,
+    outgoing: Outgoing,
+}
+
+impl<T: AsyncReadWrite + Unpin> PulseOximeter<T> {
+    /// Split the connection into a receiving and a sending half.
+    ///
+    /// The halves can be used independently, e.g. from separate tasks, to receive packages while
+    /// keep-alives or commands are sent. Each half keeps its part of the connection state: the
+    /// receiving half continues a partially received package and the sending half finishes a
+    /// partially sent package before the next one. Use [ReceiveHalf::unsplit()] to get the
+    /// [PulseOximeter] back.
+    ///
+    /// The port is locked while one of the halves polls it, so it must not block in
+    /// [AsyncReadWrite::poll_read()] or [AsyncReadWrite::poll_write()].
+    pub fn split(self) -> (ReceiveHalf<T>, SendHalf<T>) {
+        let port = Arc::new(SharedPort(Mutex::new(self.port)));
+        let receive = ReceiveHalf {
+            port: port.clone(),
+            incoming: self.incoming,
+        };
+        let send = SendHalf {
+            port,
+            outgoing: self.outgoing,
+        };
+        (receive, send)
+    }
+}
+
+impl<T: AsyncReadWrite + Unpin> ReceiveHalf<T> {
+    /// Enable or disable resynchronization of the incoming byte stream.
+    ///
+    /// See [IncomingStateMachine::set_resynchronize()] for details.
+    pub fn set_resynchronize(&mut self, resynchronize: bool) {
+        self.incoming.set_resynchronize(resynchronize);
+    }
+
+    /// Total number of incoming bytes which were dropped during resynchronization.
+    pub fn skipped_bytes(&self) -> usize {
+        self.incoming.skipped_bytes()
+    }
+
+    /// Receive the next package from the device.
+    pub fn receive_package(
+        &mut self,
+    ) -> impl Future<Output = Result<IncomingPackage, T::Error>> + '_ {
+        future::poll_fn(move |cx| self.poll_receive(cx))
+    }
+
+    /// Continue receiving the next package
+    pub fn poll_receive(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<IncomingPackage, T::Error>> {
+        let port = &self.port;
+        self.incoming.resume(|buf| Pin::new(&mut *port.lock()).poll_read(cx, buf))
+    }
+
+    /// Join both halves to the [PulseOximeter] they were split from.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `send` was split from a different [PulseOximeter].
+    pub fn unsplit(self, send: SendHalf<T>) -> PulseOximeter<T> {
+        assert!(Arc::ptr_eq(&self.port, &send.port), "halves were split from different devices");
+        drop(send.port);
+        let port = match Arc::try_unwrap(self.port) {
+            Ok(port) => port.0.into_inner().unwrap_or_else(PoisonError::into_inner),
+            Err(_) => unreachable!("port is only shared by the two halves"),
+        };
+        PulseOximeter {
+            port,
+            incoming: self.incoming,
+            outgoing: send.outgoing,
+        }
+    }
+}
+
+impl<T: AsyncReadWrite + Unpin> SendHalf<T> {
+    /// Send a package to the device.
+    ///
+    /// See [PulseOximeter::send_package()] for details.
+    pub fn send_package<P>(&mut self, package: P) -> impl Future<Output = Result<(), T::Error>> + '_
+    where
+        P: OutgoingPackage,
+    {
+        let buffer = bytes_from_package(package);
+        let mut number = None;
+        future::poll_fn(move |cx| {
+            let port = &mut *self.port.lock();
+            self.outgoing.poll_send(cx, port, buffer, &mut number)
+        })
+    }
+
+    /// Append a package to the outgoing queue, without waiting until it is sent.
+    ///
+    /// See [PulseOximeter::queue_package()] for details.
+    pub fn queue_package<P>(&mut self, package: P) -> Result<u64, T::Error>
+    where
+        P: OutgoingPackage,
+    {
+        self.outgoing.queue(bytes_from_package(package)).ok_or(Error::QueueFull)
+    }
+
+    /// Send all queued packages and flush the port.
+    pub fn flush(&mut self) -> impl Future<Output = Result<(), T::Error>> + '_ {
+        future::poll_fn(move |cx| self.poll_outgoing(cx))
+    }
+
+    /// Number of packages which were written to the port and flushed.
+    ///
+    /// See [PulseOximeter::written_packages()] for details.
+    pub fn written_packages(&self) -> u64 {
+        self.outgoing.written()
+    }
+
+    /// Continue sending the queued packages, until all are written and flushed
+    pub fn poll_outgoing(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), T::Error>> {
+        self.outgoing.poll(cx, &mut *self.port.lock())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::incoming_package::{FreeFeedback, UserAmount};
+    use crate::mock::{real_time_data, MockPort};
+    use crate::outgoing_package::{AnyOutgoingPackage, ControlCommand};
+
+    #[test]
+    fn test_split_and_unsplit() {
+        let device = PulseOximeter::new(MockPort::new(&[
+            real_time_data(),
+            IncomingPackage::FreeFeedback(FreeFeedback {}),
+            IncomingPackage::UserAmount(UserAmount { total_user: 2 }),
+        ]));
+        let (mut receive, mut send) = device.split();
+
+        let sender = thread::spawn(move || {
+            block_on(send.send_package(ControlCommand::InformDeviceConnected)).unwrap();
+            block_on(send.send_package(ControlCommand::StopRealTimeData)).unwrap();
+            send
+        });
+        assert!(matches!(
+            block_on(receive.receive_package()).unwrap(),
+            IncomingPackage::RealTimeData(_)
+        ));
+        assert!(matches!(
+            block_on(receive.receive_package()).unwrap(),
+            IncomingPackage::FreeFeedback(_)
+        ));
+
+        let mut device = receive.unsplit(sender.join().unwrap());
+        assert_eq!(block_on(device.user_amount()).unwrap(), 2);
+        assert_eq!(device.port.sent_commands(), [
+            AnyOutgoingPackage::ControlCommand(ControlCommand::InformDeviceConnected),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::StopRealTimeData),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::AskForUserAmount),
+        ]);
+    }
+}
@@ -0,0 +1,378 @@
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use futures::{ready, Stream};
+
+use crate::incoming_package::{FeedbackCode, IncomingPackage, StorageDataIdentifiers};
+use crate::outgoing_package::{bytes_from_package, ControlCommand, OutgoingPackage};
+use crate::traits::AsyncReadWrite;
+use crate::{Error, PulseOximeter, Result};
+
+/// Single measurement of a storage segment
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StorageSample {
+    /// SpO2
+    pub spo2: u8,
+    /// Pulse rate
+    pub pulse_rate: u8,
+    /// Perfusion index, if the segment was recorded with PI
+    pub pi: Option<u16>,
+}
+
+/// Number of data bytes of a sample in a `StorageData` package
+const BYTES_PER_SAMPLE: u32 = 2;
+/// Number of data bytes of a sample in a `StorageDataWithPI` package
+const BYTES_PER_SAMPLE_WITH_PI: u32 = 4;
+
+/// Stream of the samples of a storage segment, created by [PulseOximeter::storage_data()] or
+/// [PulseOximeter::storage_data_with()].
+///
+/// `AskForStorageData` is sent when the stream is polled for the first time. Samples are unpacked
+/// from `StorageData` (three samples per package) or `StorageDataWithPI` (one sample with
+/// perfusion index per package). The format is taken from the [StorageDataIdentifiers] of the
+/// segment if they were given, otherwise from the first storage data package. Receiving the other
+/// format afterwards fails with [Error::StorageFormatMismatch], other packages are ignored. The
+/// stream ends as soon as the announced data length was received, padding samples of the last
+/// package are dropped.
+///
+/// If the stream is dropped before it ended, `StopStorageData` is sent with the next operation on
+/// the [PulseOximeter].
+pub struct StorageDataStream<'a, T: AsyncReadWrite + Unpin> {
+    device: &'a mut PulseOximeter<T>,
+    user_index: u8,
+    segment: u8,
+    length: u32,
+    /// Whether the samples are sent as `StorageDataWithPI`, `None` until the format is known
+    with_pi: Option<bool>,
+    state: State,
+    /// Number of data bytes which are still expected
+    remaining: u32,
+    /// Samples of the last package
+    buffer: [StorageSample; 3],
+    /// Number of valid samples in `buffer`
+    buffered: usize,
+    /// Index of the next sample in `buffer` to yield
+    position: usize,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum State {
+    /// `AskForStorageData` has not been sent yet
+    Idle,
+    /// Receiving storage data
+    Running,
+}
+
+impl<'a, T: AsyncReadWrite + Unpin> StorageDataStream<'a, T> {
+    pub(crate) fn new(
+        device: &'a mut PulseOximeter<T>,
+        user_index: u8,
+        segment: u8,
+        length: u32,
+        identifiers: Option<StorageDataIdentifiers>,
+    ) -> Self {
+        Self {
+            device,
+            user_index,
+            segment,
+            length,
+            with_pi: identifiers.map(|identifiers| identifiers.with_pi()),
+            state: State::Idle,
+            remaining: length,
+            buffer: [StorageSample {
+                spo2: 0,
+                pulse_rate: 0,
+                pi: None,
+            }; 3],
+            buffered: 0,
+            position: 0,
+        }
+    }
+
+    /// The announced data length of the segment (in bytes).
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// Whether the segment was recorded with perfusion index, `None` if this is not known yet.
+    ///
+    /// Without [StorageDataIdentifiers], the format is known once the first sample was received.
+    pub fn with_pi(&self) -> Option<bool> {
+        self.with_pi
+    }
+
+    /// The number of samples of the segment, `None` if the format is not known yet.
+    pub fn sample_count(&self) -> Option<u32> {
+        let bytes_per_sample = match self.with_pi? {
+            true => BYTES_PER_SAMPLE_WITH_PI,
+            false => BYTES_PER_SAMPLE,
+        };
+        Some(self.length / bytes_per_sample)
+    }
+
+    /// The underlying device.
+    pub fn device(&self) -> &PulseOximeter<T> {
+        self.device
+    }
+
+    /// Take the next unpacked sample
+    fn next_buffered(&mut self) -> Option<StorageSample> {
+        let sample = self.buffer[..self.buffered].get(self.position).copied()?;
+        self.position += 1;
+        Some(sample)
+    }
+
+    /// Unpack the samples of `package`, other packages than storage data are ignored
+    fn unpack(&mut self, package: IncomingPackage) -> Result<(), T::Error> {
+        if let IncomingPackage::StorageData(_) | IncomingPackage::StorageDataWithPI(_) = package {
+            let with_pi = matches!(package, IncomingPackage::StorageDataWithPI(_));
+            match self.with_pi {
+                Some(expected) if expected != with_pi => {
+                    return Err(Error::StorageFormatMismatch {
+                        code: package.code(),
+                        with_pi: expected,
+                    });
+                }
+                _ => self.with_pi = Some(with_pi),
+            }
+        }
+        match package {
+            IncomingPackage::StorageData(data) => {
+                // The last package is padded if the segment length is not a multiple of 3 samples
+                let count = ((self.remaining + 1) / BYTES_PER_SAMPLE).min(3);
+                self.remaining = self.remaining.saturating_sub(count * BYTES_PER_SAMPLE);
+                let samples = [
+                    (data.spo2_1, data.pulse_rate_1),
+                    (data.spo2_2, data.pulse_rate_2),
+                    (data.spo2_3, data.pulse_rate_3),
+                ];
+                for (target, (spo2, pulse_rate)) in self.buffer.iter_mut().zip(samples) {
+                    *target = StorageSample {
+                        spo2,
+                        pulse_rate,
+                        pi: None,
+                    };
+                }
+                self.buffered = count as usize;
+            }
+            IncomingPackage::StorageDataWithPI(data) => {
+                self.remaining = self.remaining.saturating_sub(BYTES_PER_SAMPLE_WITH_PI);
+                self.buffer[0] = StorageSample {
+                    spo2: data.spo2,
+                    pulse_rate: data.pulse_rate,
+                    pi: Some(data.pi),
+                };
+                self.buffered = 1;
+            }
+            _ => return Ok(()),
+        }
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<T: AsyncReadWrite + Unpin> Stream for StorageDataStream<'_, T> {
+    type Item = Result<StorageSample, T::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(sample) = this.next_buffered() {
+                return Poll::Ready(Some(Ok(sample)));
+            }
+            if this.remaining == 0 {
+                return Poll::Ready(None);
+            }
+            ready!(this.device.poll_outgoing(cx))?;
+            if this.state == State::Idle {
+                let command = ControlCommand::AskForStorageData(this.user_index, this.segment);
+                this.device.start_send(bytes_from_package(command));
+                this.state = State::Running;
+                continue;
+            }
+            let package = ready!(this.device.poll_receive(cx))?;
+            if let IncomingPackage::CommandFeedback(feedback) = package {
+                let command = ControlCommand::AskForStorageData(this.user_index, this.segment);
+                if feedback.command == command.bytes()[0]
+                    && feedback.code != FeedbackCode::Completed
+                {
+                    this.remaining = 0;
+                    return Poll::Ready(Some(Err(Error::from_feedback(feedback))));
+                }
+            }
+            if let Err(err) = this.unpack(package) {
+                this.device.send_later(ControlCommand::StopStorageData);
+                this.remaining = 0;
+                return Poll::Ready(Some(Err(err)));
+            }
+        }
+    }
+}
+
+impl<T: AsyncReadWrite + Unpin> Drop for StorageDataStream<'_, T> {
+    fn drop(&mut self) {
+        if self.state == State::Running && self.remaining > 0 {
+            self.device.send_later(ControlCommand::StopStorageData);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use futures::executor::block_on;
+    use futures::{StreamExt, TryStreamExt};
+
+    use super::*;
+    use crate::incoming_package::{StorageData, StorageDataLength, StorageDataWithPI};
+    use crate::mock::{real_time_data, MockPort};
+    use crate::outgoing_package::AnyOutgoingPackage;
+
+    fn length(length: u32) -> IncomingPackage {
+        IncomingPackage::StorageDataLength(StorageDataLength {
+            user_index: 0,
+            data_segment: 1,
+            length,
+        })
+    }
+
+    fn identifiers(pi_identifiers: u8) -> StorageDataIdentifiers {
+        StorageDataIdentifiers {
+            user_index: 0,
+            data_segment: 1,
+            pi_identifiers,
+            retention: [0; 4],
+        }
+    }
+
+    fn storage_data(first: u8) -> IncomingPackage {
+        IncomingPackage::StorageData(StorageData {
+            spo2_1: first,
+            pulse_rate_1: 60,
+            spo2_2: first + 1,
+            pulse_rate_2: 60,
+            spo2_3: first + 2,
+            pulse_rate_3: 60,
+        })
+    }
+
+    #[test]
+    fn test_storage_data() {
+        let mut device = PulseOximeter::new(MockPort::new(&[
+            length(8),
+            storage_data(90),
+            real_time_data(),
+            storage_data(93),
+            storage_data(96),
+        ]));
+        let spo2 = block_on(async {
+            let mut stream = device.storage_data(0, 1).await.unwrap();
+            assert_eq!(stream.length(), 8);
+            assert_eq!(stream.sample_count(), None);
+            assert_eq!(stream.next().await.unwrap().unwrap().spo2, 90);
+            assert_eq!(stream.with_pi(), Some(false));
+            assert_eq!(stream.sample_count(), Some(4));
+            stream.map_ok(|sample| sample.spo2).try_collect::<Vec<_>>().await.unwrap()
+        });
+        assert_eq!(spo2, [91, 92, 93]);
+        assert_eq!(device.port.sent_commands(), [
+            AnyOutgoingPackage::ControlCommand(ControlCommand::AskForStorageDataLength(0, 1)),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::AskForStorageData(0, 1)),
+        ]);
+    }
+
+    #[test]
+    fn test_storage_data_with_pi() {
+        let sample = |pi| {
+            IncomingPackage::StorageDataWithPI(StorageDataWithPI {
+                spo2: 97,
+                pulse_rate: 61,
+                pi,
+            })
+        };
+        let mut device =
+            PulseOximeter::new(MockPort::new(&[length(8), sample(120), sample(130), sample(140)]));
+        let samples = block_on(async {
+            let stream = device.storage_data_with(identifiers(0x01)).await.unwrap();
+            assert_eq!(stream.sample_count(), Some(2));
+            stream.try_collect::<Vec<_>>().await.unwrap()
+        });
+        assert_eq!(samples, [
+            StorageSample {
+                spo2: 97,
+                pulse_rate: 61,
+                pi: Some(120),
+            },
+            StorageSample {
+                spo2: 97,
+                pulse_rate: 61,
+                pi: Some(130),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_format_mismatch() {
+        let mut device = PulseOximeter::new(MockPort::new(&[length(8), storage_data(90)]));
+        block_on(async {
+            let mut stream = device.storage_data_with(identifiers(0x01)).await.unwrap();
+            let result = stream.next().await.unwrap();
+            assert!(matches!(
+                result,
+                Err(Error::StorageFormatMismatch {
+                    code: 0x0F,
+                    with_pi: true
+                })
+            ));
+            assert!(stream.next().await.is_none());
+            drop(stream);
+            device.send_package(ControlCommand::AskForUserAmount).await.unwrap();
+        });
+        assert_eq!(device.port.sent_commands()[2..], [
+            AnyOutgoingPackage::ControlCommand(ControlCommand::StopStorageData),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::AskForUserAmount),
+        ]);
+    }
+
+    #[test]
+    fn test_detected_format_mismatch() {
+        let with_pi = IncomingPackage::StorageDataWithPI(StorageDataWithPI {
+            spo2: 97,
+            pulse_rate: 61,
+            pi: 120,
+        });
+        let mut device =
+            PulseOximeter::new(MockPort::new(&[length(12), storage_data(90), with_pi]));
+        let results =
+            block_on(async { device.storage_data(0, 1).await.unwrap().collect::<Vec<_>>().await });
+        assert_eq!(results.len(), 4);
+        assert!(matches!(
+            results[3],
+            Err(Error::StorageFormatMismatch {
+                code: 0x09,
+                with_pi: false
+            })
+        ));
+    }
+
+    #[test]
+    fn test_stop_on_drop() {
+        let mut device = PulseOximeter::new(MockPort::new(&[length(12), storage_data(90)]));
+        block_on(async {
+            let mut stream = device.storage_data(0, 1).await.unwrap();
+            stream.next().await.unwrap().unwrap();
+            drop(stream);
+            device.send_package(ControlCommand::AskForUserAmount).await.unwrap();
+        });
+        assert_eq!(device.port.sent_commands(), [
+            AnyOutgoingPackage::ControlCommand(ControlCommand::AskForStorageDataLength(0, 1)),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::AskForStorageData(0, 1)),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::StopStorageData),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::AskForUserAmount),
+        ]);
+    }
+}
@@ -0,0 +1,297 @@
+//! Runtime-agnostic timeouts and retries
+//!
+//! The library does not depend on an executor, so delays are created by a [Timer]. Implementations
+//! for tokio ([TokioTimer], feature `tokio`) and embassy ([EmbassyTimer], feature `embassy-time`)
+//! are included.
+#[cfg(feature = "tokio")]
+extern crate std;
+
+use core::future::Future;
+use core::pin::pin;
+#[cfg(feature = "tokio")]
+use core::pin::Pin;
+use core::time::Duration;
+
+use futures::future::{self, Either};
+
+use crate::incoming_package::StorageDataIdentifiers;
+use crate::outgoing_package::ControlCommand;
+use crate::traits::AsyncReadWrite;
+use crate::{DateTime, Error, PulseOximeter, Result};
+
+/// Source of delays, e.g. the timer of an async runtime
+pub trait Timer {
+    /// Future which completes after the delay
+    ///
+    /// The future is stored in streams like [RealTimeDataStream](crate::RealTimeDataStream), so it
+    /// must be [Unpin]. Box the future of the runtime if it is not.
+    type Sleep: Future<Output = ()> + Unpin;
+
+    /// Create a future which completes after `duration`.
+    fn sleep(&mut self, duration: Duration) -> Self::Sleep;
+}
+
+impl<M: Timer + ?Sized> Timer for &mut M {
+    type Sleep = M::Sleep;
+
+    fn sleep(&mut self, duration: Duration) -> Self::Sleep {
+        (**self).sleep(duration)
+    }
+}
+
+/// Timeout and number of retries of a request
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Time to wait for the response to each attempt
+    pub timeout: Duration,
+    /// Number of times a request is sent again after a timeout
+    pub retries: u8,
+}
+
+impl RetryPolicy {
+    /// Wait `timeout` for the response, without retrying.
+    pub const fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            retries: 0,
+        }
+    }
+
+    /// Send a request up to `retries` more times if the device does not respond in time.
+    pub const fn with_retries(mut self, retries: u8) -> Self {
+        self.retries = retries;
+        self
+    }
+}
+
+impl Default for RetryPolicy {
+    /// One second without retries
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1))
+    }
+}
+
+/// Wait for `request`, but at most `duration`.
+///
+/// Fails with [Error::Timeout] if `request` did not complete in time.
+pub async fn timeout<
+    M: Timer,
+    R,
+    #[cfg(feature = "std")] E: snafu::AsErrorSource,
+    #[cfg(not(feature = "std"))] E,
+>(
+    timer: &mut M,
+    duration: Duration,
+    request: impl Future<Output = Result<R, E>>,
+) -> Result<R, E> {
+    match future::select(pin!(request), pin!(timer.sleep(duration))).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(Error::Timeout),
+    }
+}
+
+/// Generates the request methods of [Timed], which retry the methods of [PulseOximeter].
+///
+/// `$marker` is the command used to drop late answers after a retry, it must not be answered like
+/// the request itself.
+macro_rules! timed_requests {
+    ($(
+        $(#[$meta:meta])*
+        $name:ident($($arg:ident: $type:ty),*) -> $result:ty => $marker:ident;
+    )*) => {
+        impl<T: AsyncReadWrite + Unpin, M: Timer> Timed<'_, T, M> {
+            $(
+                $(#[$meta])*
+                pub async fn $name(&mut self, $($arg: $type),*) -> Result<$result, T::Error> {
+                    let mut attempt = 0;
+                    let result = loop {
+                        let request = self.device.$name($($arg),*);
+                        match timeout(&mut self.timer, self.policy.timeout, request).await {
+                            Err(Error::Timeout) if attempt < self.policy.retries => attempt += 1,
+                            result => break result?,
+                        }
+                    };
+                    if attempt > 0 {
+                        self.drain(ControlCommand::$marker).await?;
+                    }
+                    Ok(result)
+                }
+            )*
+        }
+    };
+}
+
+/// Requests of a [PulseOximeter] with timeout and retries, created by [PulseOximeter::timed()].
+///
+/// If the device does not respond within the timeout of the [RetryPolicy], the request is sent
+/// again, until the retries are used up and the request fails with [Error::Timeout]. Only
+/// [Timed::delete_segment()] is not retried, because it is not idempotent. A response to
+/// an earlier attempt which arrives late is accepted as the response of the current attempt.
+///
+/// After a retry, the device may still answer the other attempts. To not take these answers for
+/// the answers of later requests, another command is sent (asking for the device identifier, or the
+/// user amount for [Timed::device_identifier()]) and all packages until its answer are dropped.
+pub struct Timed<'a, T: AsyncReadWrite + Unpin, M: Timer> {
+    device: &'a mut PulseOximeter<T>,
+    timer: M,
+    policy: RetryPolicy,
+}
+
+impl<T: AsyncReadWrite + Unpin, M: Timer> Timed<'_, T, M> {
+    /// Drop the outstanding answers to earlier attempts
+    async fn drain(&mut self, marker: ControlCommand) -> Result<(), T::Error> {
+        timeout(&mut self.timer, self.policy.timeout, self.device.sync(marker)).await
+    }
+
+    /// See [PulseOximeter::delete_segment()].
+    ///
+    /// Deleting is never retried, only the timeout applies: if the first attempt succeeded and
+    /// only its answer was late, a retry would delete the segment which took over its index.
+    pub async fn delete_segment(&mut self, user_index: u8, segment: u8) -> Result<(), T::Error> {
+        let request = self.device.delete_segment(user_index, segment);
+        timeout(&mut self.timer, self.policy.timeout, request).await
+    }
+}
+
+impl<T: AsyncReadWrite + Unpin> PulseOximeter<T> {
+    /// Send requests with timeout and retries, using `timer` for the delays.
+    pub fn timed<M: Timer>(&mut self, timer: M, policy: RetryPolicy) -> Timed<'_, T, M> {
+        Timed {
+            device: self,
+            timer,
+            policy,
+        }
+    }
+}
+
+timed_requests! {
+    /// See [PulseOximeter::stop_real_time_data()].
+    stop_real_time_data() -> () => AskForDeviceIdentifier;
+    /// See [PulseOximeter::device_identifier()].
+    device_identifier() -> [u8; 7] => AskForUserAmount;
+    /// See [PulseOximeter::user_amount()].
+    user_amount() -> u8 => AskForDeviceIdentifier;
+    /// See [PulseOximeter::segment_amount()].
+    segment_amount(user_index: u8) -> u8 => AskForDeviceIdentifier;
+    /// See [PulseOximeter::storage_start_time()].
+    storage_start_time(user_index: u8, segment: u8) -> DateTime => AskForDeviceIdentifier;
+    /// See [PulseOximeter::storage_length()].
+    storage_length(user_index: u8, segment: u8) -> u32 => AskForDeviceIdentifier;
+    /// See [PulseOximeter::storage_identifiers()].
+    storage_identifiers(user_index: u8, segment: u8) -> StorageDataIdentifiers
+        => AskForDeviceIdentifier;
+    /// See [PulseOximeter::set_date_time()].
+    set_date_time(date_time: DateTime) -> () => AskForDeviceIdentifier;
+}
+
+/// [Timer] of the tokio runtime
+#[cfg(feature = "tokio")]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TokioTimer;
+
+#[cfg(feature = "tokio")]
+impl Timer for TokioTimer {
+    type Sleep = Pin<std::boxed::Box<tokio::time::Sleep>>;
+
+    fn sleep(&mut self, duration: Duration) -> Self::Sleep {
+        std::boxed::Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// [Timer] of embassy, e.g. on a microcontroller
+#[cfg(feature = "embassy-time")]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct EmbassyTimer;
+
+#[cfg(feature = "embassy-time")]
+impl Timer for EmbassyTimer {
+    type Sleep = embassy_time::Timer;
+
+    fn sleep(&mut self, duration: Duration) -> Self::Sleep {
+        let micros = duration.as_micros().try_into().unwrap_or(u64::MAX);
+        embassy_time::Timer::after(embassy_time::Duration::from_micros(micros))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::incoming_package::{
+        CommandFeedback, DeviceIdentifier, FeedbackCode, IncomingPackage, UserAmount,
+    };
+    use crate::mock::{MockPort, MockTimer, SilentPort};
+    use crate::outgoing_package::{AnyOutgoingPackage, ControlCommand};
+
+    #[test]
+    fn test_retry() {
+        let user_amount = |total_user| IncomingPackage::UserAmount(UserAmount { total_user });
+        // Both attempts are answered late
+        let mut device = PulseOximeter::new(SilentPort {
+            mock: MockPort::new(&[
+                user_amount(2),
+                user_amount(2),
+                IncomingPackage::DeviceIdentifier(DeviceIdentifier {
+                    identifier: *b"PO_250a",
+                }),
+                user_amount(3),
+            ]),
+            ignored: 1,
+        });
+        let policy = RetryPolicy::default().with_retries(1);
+        let mut timed = device.timed(MockTimer { elapsed: 1 }, policy);
+        assert_eq!(block_on(timed.user_amount()).unwrap(), 2);
+        // The late answer to the second attempt was dropped
+        assert_eq!(block_on(device.user_amount()).unwrap(), 3);
+        assert_eq!(
+            device.port.mock.sent_commands(),
+            [
+                ControlCommand::AskForUserAmount,
+                ControlCommand::AskForUserAmount,
+                ControlCommand::AskForDeviceIdentifier,
+                ControlCommand::AskForUserAmount,
+            ]
+            .map(AnyOutgoingPackage::ControlCommand)
+        );
+    }
+
+    #[test]
+    fn test_delete_not_retried() {
+        // The first answer is late, after the delete already timed out
+        let mut device = PulseOximeter::new(SilentPort {
+            mock: MockPort::new(&[
+                IncomingPackage::CommandFeedback(CommandFeedback {
+                    command: 0xAE,
+                    code: FeedbackCode::Completed,
+                }),
+                IncomingPackage::UserAmount(UserAmount { total_user: 1 }),
+            ]),
+            ignored: 1,
+        });
+        let policy = RetryPolicy::default().with_retries(2);
+        let result = block_on(device.timed(MockTimer { elapsed: 1 }, policy).delete_segment(0, 1));
+        assert!(matches!(result, Err(Error::Timeout)));
+        assert_eq!(block_on(device.user_amount()).unwrap(), 1);
+        assert_eq!(
+            device.port.mock.sent_commands(),
+            [
+                ControlCommand::DeleteStorageData(0, 1),
+                ControlCommand::AskForUserAmount,
+            ]
+            .map(AnyOutgoingPackage::ControlCommand)
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut device = PulseOximeter::new(SilentPort {
+            mock: MockPort::new(&[]),
+            ignored: 0,
+        });
+        let policy = RetryPolicy::default().with_retries(2);
+        let result = block_on(device.timed(MockTimer { elapsed: 3 }, policy).user_amount());
+        assert!(matches!(result, Err(Error::Timeout)));
+        assert_eq!(device.port.mock.sent_commands().len(), 3);
+    }
+}
@@ -0,0 +1,68 @@
+extern crate std;
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use std::io;
+
+use futures::ready;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::traits::AsyncReadWrite;
+
+/// Adapter which implements [AsyncReadWrite] for [tokio] ports, e.g. `tokio_serial::SerialStream`
+/// or `tokio::net::TcpStream`.
+///
+/// A separate adapter is necessary, because [AsyncReadWrite] is already implemented for all ports
+/// implementing the [futures::io] traits.
+pub struct TokioIo<T>(pub T);
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncReadWrite for TokioIo<T> {
+    type Error = io::Error;
+
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(Pin::new(&mut self.get_mut().0).poll_read(cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::incoming_package::{IncomingPackage, UserAmount};
+    use crate::outgoing_package::{bytes_from_package, ControlCommand};
+    use crate::PulseOximeter;
+
+    #[test]
+    fn test_tokio_io() {
+        let (port, mut device_side) = duplex(64);
+        let mut device = PulseOximeter::new(TokioIo(port));
+        block_on(async {
+            let package = IncomingPackage::UserAmount(UserAmount { total_user: 3 });
+            device_side.write_all(&package.to_bytes()).await.unwrap();
+            assert_eq!(device.user_amount().await.unwrap(), 3);
+            let mut request = [0; 9];
+            device_side.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, bytes_from_package(ControlCommand::AskForUserAmount));
+        });
+    }
+}
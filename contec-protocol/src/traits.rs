@@ -6,6 +6,7 @@ pub trait AsyncReadWrite {
     /// Device error
     #[cfg(feature = "std")]
     type Error: snafu::AsErrorSource;
+    /// Device error
     #[cfg(not(feature = "std"))]
     type Error;
 
@@ -22,6 +23,13 @@ pub trait AsyncReadWrite {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, Self::Error>>;
+
+    /// Attempt to flush the object, ensuring that all written bytes reach the device.
+    ///
+    /// Does nothing by default, ports which buffer written bytes should override it.
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
 }
 
 #[cfg(feature = "std")]
@@ -46,4 +54,8 @@ where
     ) -> Poll<Result<usize, Self::Error>> {
         futures::io::AsyncWrite::poll_write(self, cx, buf)
     }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        futures::io::AsyncWrite::poll_flush(self, cx)
+    }
 }
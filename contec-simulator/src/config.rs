@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use contec_protocol::incoming_package::StorageDataIdentifiers;
+use contec_protocol::DateTime;
+
+/// Configuration of a simulated device
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Device identifier
+    pub device_id: [u8; 7],
+    /// SpO2 in real time data
+    pub spo2: u8,
+    /// Pulse rate in real time data (beats per minute)
+    pub pulse_rate: u8,
+    /// Perfusion index in real time data, `None` if the device does not support PI
+    pub pi: Option<u16>,
+    /// Users and their storage segments
+    pub users: Vec<User>,
+    /// Stop real time data if the device was not informed that it is still connected within
+    /// this duration
+    pub keep_alive_timeout: Option<Duration>,
+    /// Speak the legacy protocol, i.e. send 5 byte real time data frames and ignore all commands
+    pub legacy: bool,
+    /// Faults which are injected when answering commands
+    ///
+    /// Each entry is applied once, to the first command with the given command code (e.g. `0xAE`
+    /// for `DeleteStorageData`).
+    pub faults: Vec<(u8, Fault)>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            device_id: *b"SIMULAT",
+            spo2: 98,
+            pulse_rate: 72,
+            pi: None,
+            users: vec![User::default()],
+            keep_alive_timeout: None,
+            legacy: false,
+            faults: vec![],
+        }
+    }
+}
+
+/// User of a simulated device
+#[derive(Debug, Clone, Default)]
+pub struct User {
+    /// User information
+    pub info: [u8; 6],
+    /// Storage segments
+    pub segments: Vec<Segment>,
+}
+
+/// Storage segment of a simulated device
+#[derive(Debug, Clone)]
+pub struct Segment {
+    /// Time at which the recording started
+    pub start: DateTime,
+    /// Recorded samples
+    ///
+    /// If any sample contains a perfusion index, the segment is sent as `StorageDataWithPI`.
+    pub samples: Vec<Sample>,
+}
+
+impl Segment {
+    /// Whether this segment is sent as `StorageDataWithPI`
+    pub(crate) fn with_pi(&self) -> bool {
+        self.samples.iter().any(|sample| sample.pi.is_some())
+    }
+
+    /// Length of the segment data in bytes
+    pub(crate) fn length(&self) -> u32 {
+        let bytes_per_sample = if self.with_pi() { 4 } else { 2 };
+        self.samples.len() as u32 * bytes_per_sample
+    }
+
+    /// Storage data identifiers of this segment
+    pub(crate) fn identifiers(&self, user_index: u8, data_segment: u8) -> StorageDataIdentifiers {
+        StorageDataIdentifiers {
+            user_index,
+            data_segment,
+            pi_identifiers: if self.with_pi() { 0x01 } else { 0x00 },
+            retention: [0; 4],
+        }
+    }
+}
+
+/// Single stored measurement
+#[derive(Debug, Copy, Clone)]
+pub struct Sample {
+    /// SpO2
+    pub spo2: u8,
+    /// Pulse rate
+    pub pulse_rate: u8,
+    /// Perfusion index
+    pub pi: Option<u16>,
+}
+
+/// Fault injected when answering a command
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// Do not answer the command
+    Timeout,
+    /// Send the given bytes before answering the command
+    BadBytes(Vec<u8>),
+    /// Answer the command with a "Not supported" command feedback
+    NotSupported,
+}
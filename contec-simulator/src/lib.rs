@@ -0,0 +1,630 @@
+//! Virtual Contec pulse oximeter (protocol V7.0) for testing without hardware
+//!
+//! The [Simulator] answers commands like a real device and can be passed directly to
+//! [PulseOximeter::new()](contec_protocol::PulseOximeter::new).
+#![warn(missing_docs)]
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use contec_protocol::incoming_package::{
+    CommandFeedback, DeviceIdentifier, FeedbackCode, FreeFeedback, IncomingPackage, PIIdentifiers,
+    PISupport, RealTimeData, StorageData, StorageDataLength, StorageDataSegmentAmount,
+    StorageDataWithPI, StorageStartTimeDate, StorageStartTimeTime, UserAmount, UserInformation,
+};
+use contec_protocol::legacy;
+use contec_protocol::outgoing_package::{
+    package_from_bytes, AnyOutgoingPackage, ControlCommand, OutgoingPackage,
+};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures_timer::Delay;
+
+mod config;
+pub use config::{Config, Fault, Sample, Segment, User};
+pub use contec_protocol::DateTime;
+
+#[cfg(unix)]
+pub mod pty;
+
+mod waveform;
+use waveform::{Waveform, SAMPLE_RATE};
+
+/// Simulated pulse oximeter
+///
+/// Implements [AsyncRead] and [AsyncWrite], so it can be used in place of a serial port.
+pub struct Simulator {
+    config: Config,
+    /// Bytes of the package which is currently received from the host
+    frame: Vec<u8>,
+    /// Bytes waiting to be read by the host
+    output: VecDeque<u8>,
+    /// Ongoing real time data transmission
+    realtime: Option<Realtime>,
+    /// Ongoing storage data transmission
+    storage: Option<StorageDownload>,
+    /// Timer for the next real time data sample
+    delay: Option<Delay>,
+    /// Waker of a pending read operation
+    reader: Option<Waker>,
+    commands: CommandLog,
+}
+
+struct Realtime {
+    start: Instant,
+    sent_samples: u64,
+    waveform: Waveform,
+    last_keep_alive: Instant,
+}
+
+struct StorageDownload {
+    samples: Vec<Sample>,
+    with_pi: bool,
+    position: usize,
+}
+
+/// Packages received by a [Simulator]
+#[derive(Debug, Clone, Default)]
+pub struct CommandLog(Arc<Mutex<Vec<AnyOutgoingPackage>>>);
+
+impl CommandLog {
+    /// All packages received so far
+    pub fn commands(&self) -> Vec<AnyOutgoingPackage> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn push(&self, package: AnyOutgoingPackage) {
+        self.0.lock().unwrap().push(package);
+    }
+}
+
+impl Simulator {
+    /// Create a new simulated device
+    pub fn new(config: Config) -> Self {
+        // Legacy devices send real time data right away
+        let realtime = config.legacy.then(Realtime::new);
+        Self {
+            config,
+            frame: Vec::with_capacity(9),
+            output: VecDeque::new(),
+            realtime,
+            storage: None,
+            delay: None,
+            reader: None,
+            commands: CommandLog::default(),
+        }
+    }
+
+    /// Get a handle to the log of all packages received by this device
+    pub fn command_log(&self) -> CommandLog {
+        self.commands.clone()
+    }
+
+    /// Process a single byte sent by the host
+    fn receive_byte(&mut self, byte: u8) {
+        if byte & 0x80 == 0 {
+            // Type code, start of a new package
+            self.frame.clear();
+        } else if self.frame.is_empty() {
+            // Not synchronized, drop byte
+            return;
+        }
+        self.frame.push(byte);
+        if self.frame.len() == 9 {
+            let bytes = self.frame[..].try_into().unwrap();
+            self.frame.clear();
+            if let Ok(package) = package_from_bytes(bytes) {
+                self.handle_package(package);
+            }
+        }
+    }
+
+    /// Answer a package sent by the host
+    fn handle_package(&mut self, package: AnyOutgoingPackage) {
+        self.commands.push(package);
+        if self.config.legacy {
+            // Legacy devices do not understand any commands
+            return;
+        }
+        let command = match package {
+            AnyOutgoingPackage::ControlCommand(command) => command,
+            AnyOutgoingPackage::SetDeviceId(id) => {
+                self.config.device_id = *id.id();
+                return;
+            }
+        };
+
+        let code = command.bytes()[0];
+        if let Some(index) = self.config.faults.iter().position(|(c, _)| *c == code) {
+            match self.config.faults.remove(index).1 {
+                Fault::Timeout => return,
+                Fault::BadBytes(bytes) => self.output.extend(bytes),
+                Fault::NotSupported => {
+                    self.feedback(code, FeedbackCode::NotSupported);
+                    return;
+                }
+            }
+        }
+
+        match command {
+            ControlCommand::ContinuousRealTimeData => self.realtime = Some(Realtime::new()),
+            ControlCommand::StopRealTimeData => {
+                self.realtime = None;
+                self.send(IncomingPackage::FreeFeedback(FreeFeedback {}));
+            }
+            ControlCommand::InformDeviceConnected => {
+                if let Some(ref mut realtime) = self.realtime {
+                    realtime.last_keep_alive = Instant::now();
+                }
+            }
+            ControlCommand::AskForDeviceIdentifier => {
+                self.send(IncomingPackage::DeviceIdentifier(DeviceIdentifier {
+                    identifier: self.config.device_id,
+                }));
+            }
+            ControlCommand::AskForUserAmount => {
+                self.send(IncomingPackage::UserAmount(UserAmount {
+                    total_user: self.config.users.len() as u8,
+                }));
+            }
+            ControlCommand::AskForUserInformation(user_index) => {
+                match self.config.users.get(user_index as usize) {
+                    Some(user) => self.send(IncomingPackage::UserInformation(UserInformation {
+                        user_index,
+                        user_info: user.info,
+                    })),
+                    None => self.feedback(code, FeedbackCode::NotSupported),
+                }
+            }
+            ControlCommand::AskForStorageDataSegmentAmount(user_index) => {
+                match self.config.users.get(user_index as usize) {
+                    Some(user) => self.send(IncomingPackage::StorageDataSegmentAmount(
+                        StorageDataSegmentAmount {
+                            user_index,
+                            segment_amount: user.segments.len() as u8,
+                        },
+                    )),
+                    None => self.feedback(code, FeedbackCode::NotSupported),
+                }
+            }
+            ControlCommand::AskForStorageStartTime(user_index, segment) => {
+                match self.segment(user_index, segment).map(|s| s.start) {
+                    Some(start) => {
+                        self.send(IncomingPackage::StorageStartTimeDate(StorageStartTimeDate {
+                            user_index,
+                            storage_segment: segment,
+                            year: start.year,
+                            month: start.month,
+                            day: start.day,
+                        }));
+                        self.send(IncomingPackage::StorageStartTimeTime(StorageStartTimeTime {
+                            user_index,
+                            storage_segment: segment,
+                            hour: start.hour,
+                            minute: start.minute,
+                            second: start.second,
+                        }));
+                    }
+                    None => self.feedback(code, FeedbackCode::NotSupported),
+                }
+            }
+            ControlCommand::AskForStorageDataLength(user_index, segment) => {
+                match self.segment(user_index, segment).map(Segment::length) {
+                    Some(length) => {
+                        self.send(IncomingPackage::StorageDataLength(StorageDataLength {
+                            user_index,
+                            data_segment: segment,
+                            length,
+                        }))
+                    }
+                    None => self.feedback(code, FeedbackCode::NotSupported),
+                }
+            }
+            ControlCommand::AskForStorageData(user_index, segment) => {
+                match self.segment(user_index, segment) {
+                    Some(segment) => {
+                        self.storage = Some(StorageDownload {
+                            samples: segment.samples.clone(),
+                            with_pi: segment.with_pi(),
+                            position: 0,
+                        })
+                    }
+                    None => self.feedback(code, FeedbackCode::NotSupported),
+                }
+            }
+            ControlCommand::StopStorageData => self.storage = None,
+            ControlCommand::AskWhetherSupportPI => {
+                self.send(IncomingPackage::PIIdentifiers(PIIdentifiers {
+                    pi_support: if self.config.pi.is_some() {
+                        PISupport::Supported
+                    } else {
+                        PISupport::NotSupported
+                    },
+                }));
+            }
+            ControlCommand::AskForStorageDataIdentifiers => {
+                let identifiers = self
+                    .config
+                    .users
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(user_index, user)| {
+                        user.segments.iter().enumerate().map(move |(data_segment, segment)| {
+                            segment.identifiers(user_index as u8, data_segment as u8)
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                for identifier in identifiers {
+                    self.send(IncomingPackage::StorageDataIdentifiers(identifier));
+                }
+            }
+            ControlCommand::DeleteStorageData(user_index, segment) => {
+                let segments =
+                    self.config.users.get_mut(user_index as usize).map(|u| &mut u.segments);
+                match segments {
+                    Some(segments) if (segment as usize) < segments.len() => {
+                        segments.remove(segment as usize);
+                        self.feedback(code, FeedbackCode::Completed);
+                    }
+                    _ => self.feedback(code, FeedbackCode::DeleteFailed),
+                }
+            }
+            ControlCommand::SynchronizeDeviceTime(..)
+            | ControlCommand::SynchronizeDeviceDate(..) => {
+                self.feedback(code, FeedbackCode::Completed)
+            }
+            // Never decoded, code 0xA3 is `AskForStorageDataSegmentAmount`
+            #[allow(deprecated)]
+            ControlCommand::AskForStorageDataIdentifiers2(..) => {
+                self.feedback(code, FeedbackCode::NotSupported)
+            }
+        }
+    }
+
+    fn segment(&self, user_index: u8, segment: u8) -> Option<&Segment> {
+        self.config.users.get(user_index as usize)?.segments.get(segment as usize)
+    }
+
+    fn send(&mut self, package: IncomingPackage) {
+        self.output.extend(package.to_bytes().iter());
+    }
+
+    fn feedback(&mut self, command: u8, code: FeedbackCode) {
+        self.send(IncomingPackage::CommandFeedback(CommandFeedback { command, code }));
+    }
+
+    /// Queue the next package of an ongoing transmission
+    ///
+    /// Returns `Poll::Pending` if no package is due yet.
+    fn poll_next_package(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            if let Some(ref mut storage) = self.storage {
+                match storage.next_package() {
+                    Some(package) => {
+                        self.send(package);
+                        return Poll::Ready(());
+                    }
+                    None => self.storage = None,
+                }
+            }
+
+            let realtime = match self.realtime {
+                Some(ref mut realtime) => realtime,
+                None => return Poll::Pending,
+            };
+            let now = Instant::now();
+            if let Some(timeout) = self.config.keep_alive_timeout {
+                if !self.config.legacy && now - realtime.last_keep_alive > timeout {
+                    self.realtime = None;
+                    return Poll::Pending;
+                }
+            }
+
+            let due_samples =
+                ((now - realtime.start).as_secs_f64() * f64::from(SAMPLE_RATE)) as u64 + 1;
+            if due_samples > realtime.sent_samples {
+                // Drop samples if the host does not keep up
+                if due_samples - realtime.sent_samples > u64::from(SAMPLE_RATE) {
+                    realtime.sent_samples = due_samples - 1;
+                }
+                realtime.sent_samples += 1;
+                let (pulse_waveform, pulse_beep) =
+                    realtime.waveform.next_sample(self.config.pulse_rate);
+                let data = RealTimeData {
+                    signal_strength: 8,
+                    searching_time_too_long: false,
+                    low_spo2: self.config.spo2 < 90,
+                    pulse_beep,
+                    probe_errors: false,
+                    pulse_waveform,
+                    searching_pulse: false,
+                    bar_graph: pulse_waveform / 8,
+                    pi_invalid: self.config.pi.is_none(),
+                    pulse_rate: self.config.pulse_rate,
+                    spo2: self.config.spo2,
+                    pi: self.config.pi.unwrap_or(0),
+                };
+                if self.config.legacy {
+                    self.output.extend(legacy::encode(&data));
+                } else {
+                    self.send(IncomingPackage::RealTimeData(data));
+                }
+                return Poll::Ready(());
+            }
+
+            // Wait for the next sample
+            let next = realtime.start
+                + Duration::from_secs_f64(realtime.sent_samples as f64 / f64::from(SAMPLE_RATE));
+            let delay = self.delay.get_or_insert_with(|| Delay::new(next - now));
+            if Pin::new(delay).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.delay = None;
+        }
+    }
+}
+
+impl Realtime {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            sent_samples: 0,
+            waveform: Waveform::new(),
+            last_keep_alive: now,
+        }
+    }
+}
+
+impl StorageDownload {
+    fn next_package(&mut self) -> Option<IncomingPackage> {
+        let remaining = &self.samples[self.position..];
+        if remaining.is_empty() {
+            return None;
+        }
+        if self.with_pi {
+            let sample = remaining[0];
+            self.position += 1;
+            Some(IncomingPackage::StorageDataWithPI(StorageDataWithPI {
+                spo2: sample.spo2,
+                pulse_rate: sample.pulse_rate,
+                pi: sample.pi.unwrap_or(0),
+            }))
+        } else {
+            let mut samples = [(0, 0); 3];
+            for (target, sample) in samples.iter_mut().zip(remaining) {
+                *target = (sample.spo2, sample.pulse_rate);
+            }
+            self.position += remaining.len().min(3);
+            let [(spo2_1, pulse_rate_1), (spo2_2, pulse_rate_2), (spo2_3, pulse_rate_3)] = samples;
+            Some(IncomingPackage::StorageData(StorageData {
+                spo2_1,
+                pulse_rate_1,
+                spo2_2,
+                pulse_rate_2,
+                spo2_3,
+                pulse_rate_3,
+            }))
+        }
+    }
+}
+
+impl AsyncRead for Simulator {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.output.is_empty() && this.poll_next_package(cx).is_pending() {
+            this.reader = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let count = buf.len().min(this.output.len());
+        for (target, byte) in buf.iter_mut().zip(this.output.drain(..count)) {
+            *target = byte;
+        }
+        Poll::Ready(Ok(count))
+    }
+}
+
+impl AsyncWrite for Simulator {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        for byte in buf {
+            this.receive_byte(*byte);
+        }
+        if let Some(reader) = this.reader.take() {
+            reader.wake();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use contec_protocol::{LegacyPulseOximeter, PulseOximeter, RealTimeDevice, Timer};
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    use super::*;
+
+    /// Timer for the keep-alive of real time data
+    struct DelayTimer;
+
+    impl Timer for DelayTimer {
+        type Sleep = Delay;
+
+        fn sleep(&mut self, duration: Duration) -> Delay {
+            Delay::new(duration)
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            spo2: 95,
+            pulse_rate: 80,
+            users: vec![User {
+                info: [0; 6],
+                segments: vec![Segment {
+                    start: DateTime {
+                        year: 2022,
+                        month: 6,
+                        day: 20,
+                        hour: 22,
+                        minute: 15,
+                        second: 0,
+                    },
+                    samples: (0..4)
+                        .map(|i| Sample {
+                            spo2: 90 + i,
+                            pulse_rate: 60 + i,
+                            pi: None,
+                        })
+                        .collect(),
+                }],
+            }],
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_stop_real_time_data() {
+        let mut device = PulseOximeter::new(Simulator::new(config()));
+        block_on(async {
+            device.send_package(ControlCommand::StopRealTimeData).await.unwrap();
+            let package = device.receive_package().await.unwrap();
+            assert!(matches!(package, IncomingPackage::FreeFeedback(_)));
+        });
+    }
+
+    #[test]
+    fn test_real_time_data() {
+        let mut device = PulseOximeter::new(Simulator::new(config()));
+        block_on(async {
+            device.send_package(ControlCommand::ContinuousRealTimeData).await.unwrap();
+            for _ in 0..5 {
+                match device.receive_package().await.unwrap() {
+                    IncomingPackage::RealTimeData(data) => {
+                        assert_eq!(data.spo2, 95);
+                        assert_eq!(data.pulse_rate, 80);
+                    }
+                    p => panic!("Unexpected package {p:?}"),
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_storage_data() {
+        let mut device = PulseOximeter::new(Simulator::new(config()));
+        block_on(async {
+            device
+                .send_package(ControlCommand::AskForStorageDataLength(0, 0))
+                .await
+                .unwrap();
+            match device.receive_package().await.unwrap() {
+                IncomingPackage::StorageDataLength(length) => assert_eq!(length.length, 8),
+                p => panic!("Unexpected package {p:?}"),
+            }
+            device.send_package(ControlCommand::AskForStorageData(0, 0)).await.unwrap();
+            match device.receive_package().await.unwrap() {
+                IncomingPackage::StorageData(data) => {
+                    assert_eq!((data.spo2_1, data.pulse_rate_1), (90, 60));
+                    assert_eq!((data.spo2_3, data.pulse_rate_3), (92, 62));
+                }
+                p => panic!("Unexpected package {p:?}"),
+            }
+            match device.receive_package().await.unwrap() {
+                IncomingPackage::StorageData(data) => {
+                    assert_eq!((data.spo2_1, data.pulse_rate_1), (93, 63));
+                    assert_eq!((data.spo2_2, data.pulse_rate_2), (0, 0));
+                }
+                p => panic!("Unexpected package {p:?}"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_storage_identifiers() {
+        let mut device = PulseOximeter::new(Simulator::new(config()));
+        block_on(async {
+            let identifiers = device.storage_identifiers(0, 0).await.unwrap();
+            assert_eq!(identifiers.data_segment, 0);
+            assert!(!identifiers.with_pi());
+        });
+    }
+
+    #[test]
+    fn test_legacy() {
+        let mut simulator = Simulator::new(Config {
+            legacy: true,
+            ..config()
+        });
+        block_on(async {
+            let protocol = contec_protocol::detect_protocol(&mut simulator).await.unwrap();
+            assert_eq!(protocol, contec_protocol::Protocol::Legacy);
+            let mut device = LegacyPulseOximeter::new(simulator);
+            let mut stream = device.realtime(DelayTimer);
+            let data = stream.next().await.unwrap().unwrap();
+            assert_eq!((data.spo2, data.pulse_rate), (95, 80));
+        });
+    }
+
+    #[test]
+    fn test_faults() {
+        let mut simulator = Simulator::new(Config {
+            faults: vec![
+                (0xAD, Fault::BadBytes(vec![0x42, 0xFF])),
+                (0xAE, Fault::NotSupported),
+            ],
+            ..config()
+        });
+        let log = simulator.command_log();
+        simulator.config.faults.push((0xAD, Fault::Timeout));
+        let mut device = PulseOximeter::new(simulator);
+        device.set_resynchronize(true);
+        block_on(async {
+            device.send_package(ControlCommand::AskForUserAmount).await.unwrap();
+            let package = device.receive_package().await.unwrap();
+            assert!(matches!(package, IncomingPackage::UserAmount(UserAmount { total_user: 1 })));
+            assert_eq!(device.skipped_bytes(), 2);
+
+            device.send_package(ControlCommand::DeleteStorageData(0, 0)).await.unwrap();
+            match device.receive_package().await.unwrap() {
+                IncomingPackage::CommandFeedback(feedback) => {
+                    assert_eq!(feedback.code, FeedbackCode::NotSupported)
+                }
+                p => panic!("Unexpected package {p:?}"),
+            }
+
+            // Second `AskForUserAmount` is not answered
+            device.send_package(ControlCommand::AskForUserAmount).await.unwrap();
+            device.send_package(ControlCommand::StopRealTimeData).await.unwrap();
+            let package = device.receive_package().await.unwrap();
+            assert!(matches!(package, IncomingPackage::FreeFeedback(_)));
+        });
+        assert_eq!(log.commands(), [
+            AnyOutgoingPackage::ControlCommand(ControlCommand::AskForUserAmount),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::DeleteStorageData(0, 0)),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::AskForUserAmount),
+            AnyOutgoingPackage::ControlCommand(ControlCommand::StopRealTimeData),
+        ]);
+    }
+}
@@ -0,0 +1,92 @@
+use clap::Parser;
+use contec_simulator::{Config, DateTime, Sample, Segment, User};
+
+#[derive(Parser, Debug)]
+#[clap(author, version,
+    about = "Emulate a Contec pulse oximeter on a virtual serial port",
+    long_about = None
+)]
+struct Cli {
+    /// SpO2 in real time data
+    #[clap(long, default_value_t = 98)]
+    spo2: u8,
+
+    /// Pulse rate in real time data
+    #[clap(long, default_value_t = 72)]
+    pulse_rate: u8,
+
+    /// Perfusion index in real time and storage data (PI is not supported if omitted)
+    #[clap(long)]
+    pi: Option<u16>,
+
+    /// Number of users
+    #[clap(long, default_value_t = 1)]
+    users: u8,
+
+    /// Number of storage segments per user
+    #[clap(long, default_value_t = 1)]
+    segments: u8,
+
+    /// Number of samples per storage segment
+    #[clap(long, default_value_t = 600)]
+    samples: usize,
+
+    /// Speak the legacy protocol, which only supports real time data
+    #[clap(long)]
+    legacy: bool,
+}
+
+impl Cli {
+    fn config(&self) -> Config {
+        let segment = |index: u8| Segment {
+            start: DateTime {
+                year: 2022,
+                month: 1,
+                day: 1 + index,
+                hour: 22,
+                minute: 0,
+                second: 0,
+            },
+            samples: (0..self.samples)
+                .map(|i| Sample {
+                    spo2: self.spo2.saturating_sub((i % 4) as u8),
+                    pulse_rate: self.pulse_rate.saturating_add((i % 8) as u8),
+                    pi: self.pi,
+                })
+                .collect(),
+        };
+        Config {
+            spo2: self.spo2,
+            pulse_rate: self.pulse_rate,
+            pi: self.pi,
+            legacy: self.legacy,
+            users: (0..self.users)
+                .map(|_| User {
+                    info: [0; 6],
+                    segments: (0..self.segments).map(segment).collect(),
+                })
+                .collect(),
+            ..Config::default()
+        }
+    }
+}
+
+#[cfg(unix)]
+fn main() -> std::io::Result<()> {
+    use contec_simulator::pty::VirtualSerialPort;
+    use contec_simulator::Simulator;
+
+    let cli = Cli::parse();
+    let port = VirtualSerialPort::open(Simulator::new(cli.config()))?;
+    println!("{}", port.path().display());
+    loop {
+        std::thread::park();
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    let _ = Cli::parse().config();
+    eprintln!("pulox-sim requires pseudo-terminal support and is only available on unix systems");
+    std::process::exit(1);
+}
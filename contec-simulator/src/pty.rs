@@ -0,0 +1,67 @@
+//! Virtual serial port on a pseudo-terminal
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use futures::executor::block_on;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use nix::pty::{openpty, OpenptyResult};
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+use nix::unistd::ttyname;
+
+use crate::Simulator;
+
+/// Pseudo-terminal with a [Simulator] connected to its master side
+///
+/// Other programs can open the slave side at [VirtualSerialPort::path()] like a serial port.
+pub struct VirtualSerialPort {
+    path: PathBuf,
+    /// Keeps the slave side open, so reading from the master does not fail while no program is
+    /// connected
+    _slave: File,
+}
+
+impl VirtualSerialPort {
+    /// Open a new pseudo-terminal and connect `simulator` to it
+    pub fn open(simulator: Simulator) -> io::Result<Self> {
+        let OpenptyResult { master, slave } = openpty(None, None)?;
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+
+        // Transfer bytes unmodified
+        let mut termios = tcgetattr(slave.as_raw_fd())?;
+        cfmakeraw(&mut termios);
+        tcsetattr(slave.as_raw_fd(), SetArg::TCSANOW, &termios)?;
+        let path = ttyname(slave.as_raw_fd())?;
+
+        let (mut device_reader, mut device_writer) = simulator.split();
+        let mut master_reader = master.try_clone()?;
+        let mut master_writer = master;
+        thread::spawn(move || -> io::Result<()> {
+            let mut buffer = [0; 64];
+            loop {
+                let count = master_reader.read(&mut buffer)?;
+                block_on(device_writer.write_all(&buffer[..count]))?;
+            }
+        });
+        thread::spawn(move || -> io::Result<()> {
+            let mut buffer = [0; 64];
+            loop {
+                let count = block_on(device_reader.read(&mut buffer))?;
+                master_writer.write_all(&buffer[..count])?;
+            }
+        });
+
+        Ok(Self {
+            path,
+            _slave: slave,
+        })
+    }
+
+    /// Path of the slave side, e.g. `/dev/pts/7`
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
@@ -0,0 +1,57 @@
+use std::f32::consts::E;
+
+/// Sample rate of the real time data in Hz
+pub(crate) const SAMPLE_RATE: u32 = 60;
+
+/// Synthetic photoplethysmogram
+///
+/// Each heart beat consists of a systolic peak followed by a smaller diastolic peak.
+pub(crate) struct Waveform {
+    /// Position within the current heart beat, from `0` to `1`
+    phase: f32,
+}
+
+impl Waveform {
+    pub(crate) fn new() -> Self {
+        Self { phase: 0. }
+    }
+
+    /// Advances the waveform by one sample
+    ///
+    /// Returns the pulse waveform value (`0..=127`) and whether a new heart beat started.
+    pub(crate) fn next_sample(&mut self, pulse_rate: u8) -> (u8, bool) {
+        let value = 10. + 100. * shape(self.phase);
+        self.phase += f32::from(pulse_rate) / 60. / SAMPLE_RATE as f32;
+        let beat = self.phase >= 1.;
+        self.phase = self.phase.fract();
+        (value.clamp(0., 127.) as u8, beat)
+    }
+}
+
+/// Amplitude of the pulse wave (`0..=1`) at position `phase` within a heart beat
+fn shape(phase: f32) -> f32 {
+    fn gauss(x: f32, center: f32, width: f32) -> f32 {
+        E.powf(-((x - center) / width).powi(2))
+    }
+    gauss(phase, 0.15, 0.07) + 0.45 * gauss(phase, 0.4, 0.09)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_beats_per_minute() {
+        let mut waveform = Waveform::new();
+        let beats = (0..SAMPLE_RATE * 60).filter(|_| waveform.next_sample(72).1).count();
+        assert!((71..=72).contains(&beats));
+    }
+
+    #[test]
+    fn test_value_range() {
+        let mut waveform = Waveform::new();
+        for _ in 0..SAMPLE_RATE * 2 {
+            assert!(waveform.next_sample(200).0 <= 127);
+        }
+    }
+}
@@ -1,6 +1,5 @@
 use std::cmp::min;
 use std::ffi::CStr;
-use std::mem::size_of;
 use std::task::Poll;
 use std::task::Poll::{Pending, Ready};
 use std::{ptr, slice};
@@ -15,9 +14,9 @@ use mex::bindings::{
     mxGetUint64s_800, mxGetUint8s_800, mxIsClass_800, mxIsUint64_800, mxIsUint8_800,
     mxSetProperty_800,
 };
-use snafu::{ensure_whatever, whatever, Whatever};
+use snafu::{ensure_whatever, whatever, ResultExt, Whatever};
 
-use crate::mx_array::{create_array, get_value};
+use crate::mx_array::{create_array, get_slice, get_value};
 
 pub mod mx_array;
 
@@ -49,22 +48,8 @@ fn main([action, obj]: [*mut mxArray; 2]) -> Result<[*mut mxArray; 1]> {
         1 => get_package_bytes(ControlCommand::StopRealTimeData),
         // Return InformDeviceConnected package as bytes
         2 => get_package_bytes(ControlCommand::InformDeviceConnected),
-        // Return initial state machine
-        3 => {
-            let state_machine = IncomingStateMachine::None;
-
-            // Copy the state machine byte-for-byte into a matlab array
-            unsafe {
-                let result = mxCreateNumericMatrix_800(
-                    1,
-                    size_of::<IncomingStateMachine>(),
-                    mxClassID_mxUINT8_CLASS,
-                    mxComplexity_mxREAL,
-                );
-                ptr::copy_nonoverlapping(&state_machine, mxGetUint8s_800(result) as _, 1);
-                result
-            }
-        }
+        // Return initial state machine, serialized into a matlab array
+        3 => create_array(IncomingStateMachine::new().to_bytes()),
         // Resume state machine
         4 => {
             // Retrieve matlab Pulox object
@@ -94,21 +79,15 @@ fn main([action, obj]: [*mut mxArray; 2]) -> Result<[*mut mxArray; 1]> {
                 port
             };
             // Get the state machine from the Pulox object
-            let state_machine_buffer = unsafe {
+            let mut state_machine = unsafe {
                 let state = mxGetProperty_800(matlab_pulox, 0, mx_string!(b"state"));
                 ensure_whatever!(
                     mxIsUint8_800(state),
                     "Expected property 'state' to be of type 'uint8'"
                 );
-                ensure_whatever!(
-                    mxGetNumberOfElements_800(state) == size_of::<IncomingStateMachine>(),
-                    "Expected property 'state' to of size {}",
-                    size_of::<IncomingStateMachine>()
-                );
-                state
+                IncomingStateMachine::try_from_bytes(get_slice::<u8>(state)?)
+                    .whatever_context("Expected property 'state' to contain a state machine")?
             };
-            let state_machine: &mut IncomingStateMachine =
-                unsafe { &mut (*(mxGetUint8s_800(state_machine_buffer) as *mut _)) };
 
             // Actually resume the state machine
             let result: Poll<Result<IncomingPackage, contec_protocol::Error<snafu::Whatever>>> =
@@ -161,7 +140,8 @@ fn main([action, obj]: [*mut mxArray; 2]) -> Result<[*mut mxArray; 1]> {
                 });
             // Store changes in state machine in MATLAB object
             unsafe {
-                mxSetProperty_800(matlab_pulox, 0, mx_string!(b"state"), state_machine_buffer);
+                let state = create_array(state_machine.to_bytes());
+                mxSetProperty_800(matlab_pulox, 0, mx_string!(b"state"), state);
             }
 
             match result {
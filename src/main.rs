@@ -1,21 +1,25 @@
 mod output;
 mod realtime;
 
-use std::fmt;
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
 use std::time::Duration;
+use std::{fmt, io};
 
-use anyhow::{anyhow, bail, ensure, Context, Error, Result};
+use anyhow::{bail, Context, Result};
 use chrono::{Datelike, Local, Timelike};
 use clap::{ArgEnum, Args, Parser, Subcommand};
-use contec_protocol::incoming_package::IncomingPackage;
-use contec_protocol::outgoing_package::ControlCommand;
-use contec_protocol::PulseOximeter;
+use contec_protocol::capture::read_capture;
+use contec_protocol::incoming_package::DecodeContext;
+use contec_protocol::{
+    detect_protocol, timer, AsyncReadWrite, Capture, DateTime, LegacyPulseOximeter, Protocol,
+    PulseOximeter, RealTimeDevice, Replay, StorageDataStream, TokioIo, TokioTimer,
+};
 use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
-use futures::{AsyncRead, AsyncWrite, FutureExt};
+use futures::{FutureExt, TryStreamExt};
 use realtime::GraphTerminal;
 use tokio::time;
-use tokio::time::Instant;
-use tokio_util::compat::TokioAsyncReadCompatExt;
 
 use crate::output::{CsvWriter, OutputMode, OutputWriter, Realtime, Storage};
 use crate::realtime::{MinTerminal, RealtimeTerminal};
@@ -32,10 +36,48 @@ struct Cli {
     /// Name of serial port
     #[clap(default_value = "COM3")]
     port: String,
+
+    /// Protocol spoken by the device
+    #[clap(long, arg_enum, value_parser, default_value = "auto")]
+    protocol: ProtocolOption,
+
+    /// Capture all bytes exchanged with the device to a file
+    ///
+    /// The capture can be played back with the replay command.
+    #[clap(long, value_name = "FILE")]
+    capture: Option<String>,
+
+    /// Abort on the first invalid byte instead of skipping it
+    ///
+    /// The bytes around the invalid byte are printed when the session fails.
+    #[clap(long)]
+    strict: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ArgEnum)]
+enum ProtocolOption {
+    /// Detect the protocol automatically
+    Auto,
+    /// Protocol V7.0
+    V7,
+    /// Legacy protocol of older devices, which only supports real time data
+    Legacy,
 }
 
 #[derive(Subcommand, Debug)]
 enum Command {
+    #[clap(flatten)]
+    Device(DeviceCommand),
+
+    /// Run a command on a captured session instead of a device
+    ///
+    /// The command should be the one which was run while capturing, otherwise the device answers
+    /// do not match the requests.
+    Replay(ReplayArgs),
+}
+
+#[derive(Subcommand, Debug)]
+enum DeviceCommand {
     /// Read real time data
     ///
     /// Use --format and --output options to save the measurement data to a file.
@@ -83,6 +125,17 @@ struct StorageArgs {
     output: String,
 }
 
+#[derive(Args, Debug)]
+struct ReplayArgs {
+    /// Capture file, written with --capture
+    file: String,
+    /// Playback speed relative to the captured session, use "inf" to play back without delays
+    #[clap(long, default_value = "1", value_parser = parse_speed)]
+    speed: f64,
+    #[clap(subcommand)]
+    command: DeviceCommand,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ArgEnum)]
 enum OutputFormat {
     Csv,
@@ -109,30 +162,73 @@ impl OutputFormat {
     }
 }
 
-macro_rules! expect_package_with_timeout {
-    ($device:ident, $package:tt) => {
-        async {
-            match receive_package_with_timeout($device).await? {
-                IncomingPackage::$package(i) => Ok(i),
-                p => Err(anyhow!("Unexpected Package {p:?}")),
-            }
-        }
-    };
+/// Parse a playback speed, which must be positive
+fn parse_speed(speed: &str) -> Result<f64, String> {
+    match speed.parse::<f64>() {
+        Ok(speed) if speed > 0.0 => Ok(speed),
+        Ok(_) => Err("speed must be positive".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
 }
 
-async fn receive_package_with_timeout<T: AsyncRead + AsyncWrite + Unpin>(
-    device: &mut PulseOximeter<T>,
-) -> Result<IncomingPackage> {
-    match time::timeout(Duration::from_secs(1), device.receive_package()).await {
-        Ok(Ok(result)) => Ok(result),
-        Ok(Err(err)) => Err(Error::from(err)),
-        Err(_) => Err(Error::msg("Device did not send a response")),
-    }
+async fn with_timeout<R>(
+    request: impl Future<Output = contec_protocol::Result<R, io::Error>>,
+) -> Result<R> {
+    Ok(timer::timeout(&mut TokioTimer, Duration::from_secs(1), request).await?)
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let result = start(Cli::parse()).await;
+    if let Err(err) = &result {
+        let context = err
+            .chain()
+            .filter_map(|e| e.downcast_ref::<contec_protocol::Error<io::Error>>())
+            .find_map(|e| e.decode_context());
+        if let Some(context) = context {
+            eprint!("{}", hexdump(context));
+        }
+    }
+    result
+}
+
+/// Hexdump of the bytes around a decode error, with the failing byte marked
+fn hexdump(context: &DecodeContext) -> String {
+    let mut dump = format!(
+        "Received bytes around offset {} (after {} packages):\n",
+        context.offset, context.packages
+    );
+    for (line, bytes) in context.bytes().chunks(16).enumerate() {
+        let start = line * 16;
+        let hex = bytes.iter().map(|b| format!("{b:02X}")).collect::<Vec<_>>();
+        dump += &format!("{:08X}  {}\n", context.start_offset() + start as u64, hex.join(" "));
+        if (start..start + bytes.len()).contains(&context.position()) {
+            dump += &format!(
+                "{:10}{:>width$}\n",
+                "",
+                "^^",
+                width = 3 * (context.position() - start) + 2
+            );
+        }
+    }
+    dump
+}
+
+async fn start(cli: Cli) -> Result<()> {
+    let command = match cli.command {
+        Command::Device(command) => command,
+        Command::Replay(args) => {
+            if cli.capture.is_some() {
+                bail!("--capture cannot be used with replay");
+            }
+            let file =
+                File::open(&args.file).context(format!("Could not open capture {}", args.file))?;
+            let records = read_capture(BufReader::new(file))
+                .context(format!("Could not read capture {}", args.file))?;
+            let port = Replay::new(records, args.speed, time::sleep);
+            return run(port, args.file, cli.protocol, cli.strict, args.command).await;
+        }
+    };
 
     let port = tokio_serial::SerialStream::open(&tokio_serial::new(&cli.port, 115200)).context(
         format!(
@@ -146,31 +242,70 @@ async fn main() -> Result<()> {
                 .join(", "),
         ),
     )?;
+    let port = TokioIo(port);
 
-    let mut device = PulseOximeter::new(port.compat());
-
-    // Send StopRealTimeData and wait for FreeFeedback response
-    device.send_package(ControlCommand::StopRealTimeData).await?;
-    loop {
-        // Ignore unexpected packages
-        if let IncomingPackage::FreeFeedback(_) = receive_package_with_timeout(&mut device).await? {
-            break;
+    match cli.capture {
+        Some(capture) => {
+            let file =
+                File::create(&capture).context(format!("Could not create capture {capture}"))?;
+            run(Capture::new(port, file), cli.port, cli.protocol, cli.strict, command).await
         }
+        None => run(port, cli.port, cli.protocol, cli.strict, command).await,
     }
+}
 
-    match cli.command {
-        Command::Realtime(args) if args.no_console => {
-            realtime::<MinTerminal, _>(&mut device, args, cli.port).await
+async fn run<T: AsyncReadWrite<Error = io::Error> + Unpin>(
+    mut port: T,
+    port_name: String,
+    protocol: ProtocolOption,
+    strict: bool,
+    command: DeviceCommand,
+) -> Result<()> {
+    let detected = match protocol {
+        ProtocolOption::Auto => with_timeout(detect_protocol(&mut port))
+            .await
+            .context("Could not detect the device protocol")?,
+        ProtocolOption::V7 => Protocol::V7,
+        ProtocolOption::Legacy => Protocol::Legacy,
+    };
+
+    if detected == Protocol::Legacy {
+        let mut device = LegacyPulseOximeter::new(port);
+        return match command {
+            DeviceCommand::Realtime(args) if args.no_console => {
+                realtime::<MinTerminal, _>(&mut device, args, port_name).await
+            }
+            DeviceCommand::Realtime(args) => {
+                realtime::<GraphTerminal, _>(&mut device, args, port_name).await
+            }
+            _ => bail!("Legacy devices only support real time data"),
+        };
+    }
+
+    let mut device = PulseOximeter::new(port);
+    // Drop line noise and partial packages instead of aborting the session, unless --strict
+    device.set_resynchronize(!strict);
+
+    // Send StopRealTimeData and wait for FreeFeedback response, unless protocol detection did so
+    if protocol != ProtocolOption::Auto {
+        with_timeout(device.stop_real_time_data()).await?;
+    }
+
+    match command {
+        DeviceCommand::Realtime(args) if args.no_console => {
+            realtime::<MinTerminal, _>(&mut device, args, port_name).await
+        }
+        DeviceCommand::Realtime(args) => {
+            realtime::<GraphTerminal, _>(&mut device, args, port_name).await
         }
-        Command::Realtime(args) => realtime::<GraphTerminal, _>(&mut device, args, cli.port).await,
-        Command::Storage(args) => storage(&mut device, args).await,
-        Command::ClearStorage => clear_storage(&mut device).await,
-        Command::SyncTime => sync_time(&mut device).await,
+        DeviceCommand::Storage(args) => storage(&mut device, args).await,
+        DeviceCommand::ClearStorage => clear_storage(&mut device).await,
+        DeviceCommand::SyncTime => sync_time(&mut device).await,
     }
 }
 
-async fn realtime<T: RealtimeTerminal, U: AsyncRead + AsyncWrite + Unpin>(
-    device: &mut PulseOximeter<U>,
+async fn realtime<T: RealtimeTerminal, D: RealTimeDevice<Error = io::Error>>(
+    device: &mut D,
     args: RealtimeArgs,
     port: String,
 ) -> Result<()> {
@@ -184,14 +319,13 @@ async fn realtime<T: RealtimeTerminal, U: AsyncRead + AsyncWrite + Unpin>(
         None
     };
 
-    // Request real time data
-    device.send_package(ControlCommand::ContinuousRealTimeData).await?;
+    // Request real time data, the stream keeps the device alive
+    let mut samples = device.realtime(TokioTimer);
 
     let mut terminal_interval = time::interval(Duration::from_millis(50));
-    let mut keep_alive_interval =
-        time::interval_at(Instant::now() + Duration::from_secs(5), Duration::from_secs(5));
 
     terminal.add_message("Press ESC to exit")?;
+    let mut skipped_bytes = samples.device().skipped_bytes();
     loop {
         futures::select! {
             // Listen for Ctrl-C and ESC
@@ -202,20 +336,20 @@ async fn realtime<T: RealtimeTerminal, U: AsyncRead + AsyncWrite + Unpin>(
                     _ => {}
                 }
             }
-            // Send InformDeviceConnected every 5 seconds
-            _ = keep_alive_interval.tick().fuse() => {
-                device.send_package(ControlCommand::InformDeviceConnected).await?;
-            },
-            // Read incoming packages
-            package = receive_package_with_timeout(device).fuse() => {
-                match package? {
-                    IncomingPackage::RealTimeData(d) => {
-                        terminal.next_sample(d);
-                        if let Some(ref mut writer) = writer {
-                            writer.write_record(d).await?;
-                        }
-                    },
-                    p => bail!("Unexpected Package {p:?}"),
+            // Read real time data
+            sample = with_timeout(samples.try_next()).fuse() => {
+                let sample = sample?.context("Device stopped sending real time data")?;
+                let device = samples.device();
+                if device.skipped_bytes() > skipped_bytes {
+                    terminal.add_message(format!(
+                        "Skipped {} invalid bytes",
+                        device.skipped_bytes() - skipped_bytes
+                    ))?;
+                    skipped_bytes = device.skipped_bytes();
+                }
+                terminal.next_sample(sample);
+                if let Some(ref mut writer) = writer {
+                    writer.write_record(sample).await?;
                 }
             }
             // Update terminal
@@ -228,14 +362,14 @@ async fn realtime<T: RealtimeTerminal, U: AsyncRead + AsyncWrite + Unpin>(
     // Stop real time data
     terminal.clear_messages()?;
     terminal.add_message("Stop real time data")?;
-    device.send_package(ControlCommand::StopRealTimeData).await?;
+    with_timeout(samples.stop()).await?;
 
     terminal.close()?;
 
     Ok(())
 }
 
-async fn storage<T: AsyncRead + AsyncWrite + Unpin>(
+async fn storage<T: AsyncReadWrite<Error = io::Error> + Unpin>(
     device: &mut PulseOximeter<T>,
     args: StorageArgs,
 ) -> Result<()> {
@@ -244,64 +378,86 @@ async fn storage<T: AsyncRead + AsyncWrite + Unpin>(
     let (user_index, segment_index) = get_user_and_segment(device).await?;
 
     // Asking for Storage start time
-    device
-        .send_package(ControlCommand::AskForStorageStartTime(user_index, segment_index))
-        .await?;
-    let d = expect_package_with_timeout!(device, StorageStartTimeDate).await?;
-    let t = expect_package_with_timeout!(device, StorageStartTimeTime).await?;
+    let t = with_timeout(device.storage_start_time(user_index, segment_index)).await?;
     println!(
         "The storage start time is {}:{}:{} on {}.{}.{}",
-        t.hour, t.minute, t.second, d.year, d.month, d.day
+        t.hour, t.minute, t.second, t.year, t.month, t.day
     );
 
-    // Asking for data length
-    device
-        .send_package(ControlCommand::AskForStorageDataLength(user_index, segment_index))
-        .await?;
-    let data_length = expect_package_with_timeout!(device, StorageDataLength).await?.length;
-    println!("The storage data length is {} bytes ({} samples)", data_length, data_length / 2);
-
-    // Asking for storage data
-    device
-        .send_package(ControlCommand::AskForStorageData(user_index, segment_index))
-        .await?;
-
-    for i in (0..data_length).step_by(6) {
-        let d = expect_package_with_timeout!(device, StorageData).await?;
-        writer.write_record((d.spo2_1, d.pulse_rate_1)).await?;
-        if i + 2 < data_length {
-            writer.write_record((d.spo2_2, d.pulse_rate_2)).await?;
-        }
-        if i + 4 < data_length {
-            writer.write_record((d.spo2_3, d.pulse_rate_3)).await?;
+    // Asking for storage data identifiers, which are not supported by every device
+    let identifiers =
+        match with_timeout(device.storage_identifiers(user_index, segment_index)).await {
+            Ok(identifiers) => {
+                println!(
+                    "The storage data was recorded {} PI",
+                    if identifiers.with_pi() {
+                        "with"
+                    } else {
+                        "without"
+                    }
+                );
+                Some(identifiers)
+            }
+            Err(err) => {
+                println!("Could not read storage data identifiers: {err}");
+                None
+            }
+        };
+
+    // Asking for storage data, the format is detected from the data without identifiers
+    let mut samples = match identifiers {
+        Some(identifiers) => with_timeout(device.storage_data_with(identifiers)).await?,
+        None => with_timeout(device.storage_data(user_index, segment_index)).await?,
+    };
+    let mut length_printed = print_storage_length(&samples);
+
+    while let Some(sample) = with_timeout(samples.try_next()).await? {
+        if !length_printed {
+            length_printed = print_storage_length(&samples);
         }
+        writer.write_record(sample).await?;
+    }
+    if !length_printed {
+        println!("The storage data length is {} bytes", samples.length());
+    }
+    let skipped_bytes = samples.device().skipped_bytes();
+    if skipped_bytes > 0 {
+        println!("Warning: skipped {} invalid bytes", skipped_bytes);
     }
     println!("Finished reading and saving data");
     Ok(())
 }
 
-async fn clear_storage<T: AsyncRead + AsyncWrite + Unpin>(
+/// Print the storage data length, once the format and thus the number of samples is known
+fn print_storage_length<T: AsyncReadWrite + Unpin>(samples: &StorageDataStream<'_, T>) -> bool {
+    match samples.sample_count() {
+        Some(count) => {
+            println!("The storage data length is {} bytes ({} samples)", samples.length(), count);
+            true
+        }
+        None => false,
+    }
+}
+
+async fn clear_storage<T: AsyncReadWrite<Error = io::Error> + Unpin>(
     device: &mut PulseOximeter<T>,
 ) -> Result<()> {
     let (user_index, segment_index) = get_user_and_segment(device).await?;
 
-    // Asking for data length
-    device
-        .send_package(ControlCommand::DeleteStorageData(user_index, segment_index))
-        .await?;
-    let feedback = expect_package_with_timeout!(device, CommandFeedback).await?;
-    ensure!(feedback.code == 0, "Could not clear storage: {:?}", feedback);
+    // Deleting the data segment
+    with_timeout(device.delete_segment(user_index, segment_index))
+        .await
+        .context("Could not clear storage")?;
 
     println!("Successfully deleted segment {} for user {}", segment_index, user_index);
     Ok(())
 }
 
-async fn get_user_and_segment<T: AsyncRead + AsyncWrite + Unpin>(
+async fn get_user_and_segment<T: AsyncReadWrite<Error = io::Error> + Unpin>(
     device: &mut PulseOximeter<T>,
 ) -> Result<(u8, u8)> {
     // Asking for the amount of users
-    device.send_package(ControlCommand::AskForUserAmount).await?;
-    let user_count = expect_package_with_timeout!(device, UserAmount).await?.total_user;
+    let user_count = with_timeout(device.user_amount()).await?;
     let user_index: u8 = if user_count > 1 {
         dialoguer::Input::new()
             .with_prompt(format!("Choose the user index from 0 to {}", user_count - 1))
@@ -311,12 +467,7 @@ async fn get_user_and_segment<T: AsyncRead + AsyncWrite + Unpin>(
     };
 
     // Choosing the data segment
-    device
-        .send_package(ControlCommand::AskForStorageDataSegmentAmount(user_index))
-        .await?;
-    let segment_count = expect_package_with_timeout!(device, StorageDataSegmentAmount)
-        .await?
-        .segment_amount;
+    let segment_count = with_timeout(device.segment_amount(user_index)).await?;
     let segment_index: u8 = if user_count > 1 {
         dialoguer::Input::new()
             .with_prompt(format!("Choose the storage data segment from 0 to {}", segment_count - 1))
@@ -327,34 +478,21 @@ async fn get_user_and_segment<T: AsyncRead + AsyncWrite + Unpin>(
     Ok((user_index, segment_index))
 }
 
-async fn sync_time<T: AsyncRead + AsyncWrite + Unpin>(device: &mut PulseOximeter<T>) -> Result<()> {
+async fn sync_time<T: AsyncReadWrite<Error = io::Error> + Unpin>(
+    device: &mut PulseOximeter<T>,
+) -> Result<()> {
     let now = Local::now();
-    let year = now.year();
-    let high_year = year / 100;
-    let low_year = year - high_year;
-
-    // Sending package with current date
-    device
-        .send_package(ControlCommand::SynchronizeDeviceDate(
-            high_year as u8,
-            low_year as u8,
-            now.month() as _,
-            now.day() as _,
-            now.weekday().num_days_from_sunday() as _,
-        ))
-        .await?;
-    let feedback = expect_package_with_timeout!(device, CommandFeedback).await?;
-    ensure!(feedback.code == 0, "Could not set device time: {:?}", feedback);
-
-    device
-        .send_package(ControlCommand::SynchronizeDeviceTime(
-            now.hour() as _,
-            now.minute() as _,
-            now.second() as _,
-        ))
-        .await?;
-    let feedback = expect_package_with_timeout!(device, CommandFeedback).await?;
-    ensure!(feedback.code == 0, "Could not set device time: {:?}", feedback);
+    let date_time = DateTime {
+        year: now.year() as u16,
+        month: now.month() as u8,
+        day: now.day() as u8,
+        hour: now.hour() as u8,
+        minute: now.minute() as u8,
+        second: now.second() as u8,
+    };
+    with_timeout(device.set_date_time(date_time))
+        .await
+        .context("Could not set device time")?;
 
     println!("Successfully set device time");
     Ok(())
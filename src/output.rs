@@ -2,6 +2,7 @@ use std::future::Future;
 use std::pin::Pin;
 
 use contec_protocol::incoming_package::RealTimeData;
+use contec_protocol::StorageSample;
 use csv_async::{AsyncWriter, AsyncWriterBuilder};
 use futures::future::FutureExt;
 use tokio::fs::{File, OpenOptions};
@@ -35,13 +36,18 @@ impl OutputMode for Realtime {
     }
 }
 
+/// Storage output data
 pub struct Storage;
 impl OutputMode for Storage {
-    type DataType = (u8, u8);
-    const HEADER: &'static [&'static str] = &["Pulse rate", "Sp02"];
+    type DataType = StorageSample;
+    const HEADER: &'static [&'static str] = &["Sp02", "Pulse rate", "PI"];
 
     fn format(data: Self::DataType) -> Vec<String> {
-        vec![data.0.to_string(), data.1.to_string()]
+        vec![
+            data.spo2.to_string(),
+            data.pulse_rate.to_string(),
+            data.pi.map(|pi| pi.to_string()).unwrap_or_default(),
+        ]
     }
 }
 
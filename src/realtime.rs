@@ -17,7 +17,7 @@ use tui::{symbols, Frame, Terminal};
 pub trait RealtimeTerminal: Sized {
     fn new() -> anyhow::Result<Self>;
     fn close(&mut self) -> anyhow::Result<()>;
-    fn handle_event(&mut self) -> LocalBoxFuture<anyhow::Result<Event>>;
+    fn handle_event(&mut self) -> LocalBoxFuture<'_, anyhow::Result<Event>>;
     fn add_message(&mut self, message: impl AsRef<str>) -> anyhow::Result<()>;
     fn clear_messages(&mut self) -> anyhow::Result<()>;
     fn next_sample(&mut self, sample: RealTimeData);
@@ -43,7 +43,7 @@ impl RealtimeTerminal for MinTerminal {
         Ok(())
     }
 
-    fn handle_event(&mut self) -> LocalBoxFuture<anyhow::Result<Event>> {
+    fn handle_event(&mut self) -> LocalBoxFuture<'_, anyhow::Result<Event>> {
         async {
             match self.events.next().await {
                 Some(Ok(event)) => Ok(event),
@@ -113,7 +113,7 @@ impl RealtimeTerminal for GraphTerminal {
         Ok(())
     }
 
-    fn handle_event(&mut self) -> LocalBoxFuture<anyhow::Result<Event>> {
+    fn handle_event(&mut self) -> LocalBoxFuture<'_, anyhow::Result<Event>> {
         async {
             match self.events.next().await {
                 Some(Ok(event)) => Ok(event),
@@ -0,0 +1,247 @@
+//! End-to-end tests of the command line interface, using a simulated device on a pseudo-terminal
+#![cfg(unix)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use contec_protocol::outgoing_package::{AnyOutgoingPackage, ControlCommand};
+use contec_simulator::pty::VirtualSerialPort;
+use contec_simulator::{CommandLog, Config, DateTime, Fault, Sample, Segment, User};
+
+fn open(config: Config) -> (VirtualSerialPort, CommandLog) {
+    let simulator = contec_simulator::Simulator::new(config);
+    let log = simulator.command_log();
+    (VirtualSerialPort::open(simulator).unwrap(), log)
+}
+
+fn pulox(port: &VirtualSerialPort, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_pulox"));
+    command.arg(port.path()).args(args);
+    command
+}
+
+#[test]
+fn test_sync_time() {
+    let (port, log) = open(Config::default());
+    let output = pulox(&port, &["sync-time"]).output().unwrap();
+    assert!(output.status.success());
+
+    let commands = log.commands();
+    assert_eq!(
+        commands[0],
+        AnyOutgoingPackage::ControlCommand(ControlCommand::StopRealTimeData)
+    );
+    assert!(matches!(
+        commands[1],
+        AnyOutgoingPackage::ControlCommand(ControlCommand::SynchronizeDeviceDate(..))
+    ));
+    assert!(matches!(
+        commands[2],
+        AnyOutgoingPackage::ControlCommand(ControlCommand::SynchronizeDeviceTime(..))
+    ));
+}
+
+/// Path of a temporary file, which is unique for the current test
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "pulox-{name}-{}-{:?}",
+        std::process::id(),
+        std::thread::current().id()
+    ))
+}
+
+fn storage_config(samples: Vec<Sample>) -> Config {
+    Config {
+        users: vec![User {
+            info: [0; 6],
+            segments: vec![Segment {
+                start: DateTime {
+                    year: 2022,
+                    month: 6,
+                    day: 20,
+                    hour: 22,
+                    minute: 15,
+                    second: 0,
+                },
+                samples,
+            }],
+        }],
+        ..Config::default()
+    }
+}
+
+fn storage_csv(config: Config) -> (String, Vec<String>) {
+    let (port, _) = open(config);
+    let output = temp_file("storage.csv");
+    let result = pulox(&port, &["storage", "--format", "csv", "--output"])
+        .arg(&output)
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+
+    let csv = fs::read_to_string(&output).unwrap();
+    fs::remove_file(&output).unwrap();
+    let stdout = String::from_utf8_lossy(&result.stdout).into_owned();
+    (stdout, csv.lines().map(String::from).collect())
+}
+
+#[test]
+fn test_storage() {
+    let samples = (0..10)
+        .map(|i| Sample {
+            spo2: 90 + i,
+            pulse_rate: 60 + i,
+            pi: None,
+        })
+        .collect();
+    let (stdout, lines) = storage_csv(storage_config(samples));
+    assert!(stdout.contains("without PI"));
+    assert!(stdout.contains("20 bytes (10 samples)"));
+    assert_eq!(lines.len(), 11);
+    assert_eq!(lines[0], "Sp02,Pulse rate,PI");
+    assert_eq!(lines[1], "90,60,");
+    assert_eq!(lines[10], "99,69,");
+}
+
+#[test]
+fn test_storage_with_pi() {
+    let samples = (0..4)
+        .map(|i| Sample {
+            spo2: 95,
+            pulse_rate: 70,
+            pi: Some(100 * i),
+        })
+        .collect();
+    let (stdout, lines) = storage_csv(storage_config(samples));
+    assert!(stdout.contains("with PI"));
+    assert!(stdout.contains("16 bytes (4 samples)"));
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[1], "95,70,0");
+    assert_eq!(lines[4], "95,70,300");
+}
+
+#[test]
+fn test_storage_without_identifiers() {
+    let samples = || {
+        (0..4)
+            .map(|i| Sample {
+                spo2: 95,
+                pulse_rate: 70,
+                pi: Some(100 * i),
+            })
+            .collect()
+    };
+    for fault in [Fault::NotSupported, Fault::Timeout] {
+        let (stdout, lines) = storage_csv(Config {
+            faults: vec![(0xB0, fault)],
+            ..storage_config(samples())
+        });
+        assert!(stdout.contains("Could not read storage data identifiers"));
+        assert!(stdout.contains("16 bytes (4 samples)"));
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[4], "95,70,300");
+    }
+}
+
+#[test]
+fn test_clear_storage_not_supported() {
+    let (port, _) = open(Config {
+        faults: vec![(0xAE, contec_simulator::Fault::NotSupported)],
+        ..Config::default()
+    });
+    let output = pulox(&port, &["clear-storage"]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not supported"));
+}
+
+#[test]
+fn test_strict_hexdump() {
+    let (port, _) = open(Config {
+        faults: vec![(0xA2, contec_simulator::Fault::BadBytes(vec![0x42]))],
+        ..Config::default()
+    });
+    let output = pulox(&port, &["--strict", "--protocol", "v7", "sync-time"]).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unknown package type code 0x42"));
+    assert!(stderr.contains("00000000  42"));
+    assert!(stderr.contains("^^"));
+}
+
+#[test]
+fn test_clear_storage_legacy() {
+    let (port, log) = open(Config {
+        legacy: true,
+        ..Config::default()
+    });
+    let output = pulox(&port, &["clear-storage"]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only support real time data"));
+    assert_eq!(log.commands(), [AnyOutgoingPackage::ControlCommand(
+        ControlCommand::StopRealTimeData
+    )]);
+}
+
+#[test]
+fn test_capture_and_replay() {
+    let samples = (0..5)
+        .map(|i| Sample {
+            spo2: 90 + i,
+            pulse_rate: 60,
+            pi: None,
+        })
+        .collect();
+    let (port, _) = open(storage_config(samples));
+    let capture = temp_file("capture.txt");
+    let captured = temp_file("captured.csv");
+    let replayed = temp_file("replayed.csv");
+
+    let result = pulox(&port, &["--capture"])
+        .arg(&capture)
+        .args(["storage", "--format", "csv", "--output"])
+        .arg(&captured)
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    drop(port);
+
+    let result = Command::new(env!("CARGO_BIN_EXE_pulox"))
+        .arg("replay")
+        .arg(&capture)
+        .args(["--speed", "inf", "storage", "--format", "csv", "--output"])
+        .arg(&replayed)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    assert!(String::from_utf8_lossy(&result.stdout).contains("2022"));
+
+    let captured_csv = fs::read_to_string(&captured).unwrap();
+    assert_eq!(captured_csv.lines().count(), 6);
+    assert_eq!(fs::read_to_string(&replayed).unwrap(), captured_csv);
+
+    for speed in ["--speed=0", "--speed=NaN", "--speed=-1"] {
+        let result = Command::new(env!("CARGO_BIN_EXE_pulox"))
+            .arg("replay")
+            .arg(&capture)
+            .args([speed, "storage", "--format", "csv", "--output"])
+            .arg(&replayed)
+            .output()
+            .unwrap();
+        assert!(!result.status.success());
+        assert!(String::from_utf8_lossy(&result.stderr).contains("speed must be positive"));
+    }
+    let result = Command::new(env!("CARGO_BIN_EXE_pulox"))
+        .args(["--capture", "recapture.txt", "replay"])
+        .arg(&capture)
+        .args(["storage", "--format", "csv", "--output"])
+        .arg(&replayed)
+        .output()
+        .unwrap();
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("cannot be used with replay"));
+
+    for file in [capture, captured, replayed] {
+        fs::remove_file(file).unwrap();
+    }
+}